@@ -1,19 +1,28 @@
 use std::borrow::Borrow;
 use std::cmp::Eq;
+use std::collections::TryReserveError;
 use std::hash::Hash;
 
 use std::mem;
 
 /// Storage in a hash map
 /// All elements in a bucket are at the same hash.
+///
+/// Only exposed so that bucket arrays can be handed to and recycled by
+/// `HashMap::resize_into`.
 #[derive(Clone, Debug)]
 pub struct Bucket<K: Eq + Hash, V> {
-    pub items: Vec<(K, V)>,
+    pub(crate) items: Vec<(K, V)>,
+    /// `hashes[i]` is the cached hash of the key in `items[i]`.
+    pub(crate) hashes: Vec<u64>,
 }
 
 impl<K: Eq + Hash, V> Default for Bucket<K, V> {
     fn default() -> Self {
-        Bucket { items: Vec::new() }
+        Bucket {
+            items: Vec::new(),
+            hashes: Vec::new(),
+        }
     }
 }
 
@@ -22,10 +31,26 @@ impl<K: Eq + Hash, V> Bucket<K, V> {
         Self::default()
     }
 
-    pub fn get<Q: ?Sized>(&self, key: &Q) -> Option<&V>
+    pub fn with_capacity(capacity: usize) -> Bucket<K, V> {
+        Bucket {
+            items: Vec::with_capacity(capacity),
+            hashes: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Same as `with_capacity`, failing instead of aborting if the items
+    /// cannot be allocated.
+    pub(crate) fn try_with_capacity(capacity: usize) -> Result<Bucket<K, V>, TryReserveError> {
+        let mut bucket = Bucket::new();
+        bucket.items.try_reserve_exact(capacity)?;
+        bucket.hashes.try_reserve_exact(capacity)?;
+        Ok(bucket)
+    }
+
+    pub(crate) fn get<Q>(&self, key: &Q) -> Option<&V>
     where
         K: Borrow<Q>,
-        Q: Eq + Hash,
+        Q: Eq + Hash + ?Sized,
     {
         for (k, v) in self.items.iter() {
             if k.borrow() == key {
@@ -35,10 +60,10 @@ impl<K: Eq + Hash, V> Bucket<K, V> {
         None
     }
 
-    pub fn get_mut<Q: ?Sized>(&mut self, key: &Q) -> Option<&mut V>
+    pub(crate) fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
     where
         K: Borrow<Q>,
-        Q: Eq + Hash,
+        Q: Eq + Hash + ?Sized,
     {
         for &mut (ref k, ref mut v) in self.items.iter_mut() {
             if k.borrow() == key {
@@ -48,52 +73,155 @@ impl<K: Eq + Hash, V> Bucket<K, V> {
         None
     }
 
-    pub fn contains_key<Q: ?Sized>(&self, key: &Q) -> bool
+    pub(crate) fn contains_key<Q>(&self, key: &Q) -> bool
     where
         K: Borrow<Q>,
-        Q: Eq + Hash,
+        Q: Eq + Hash + ?Sized,
     {
-        self.items.iter().any(|&(ref k, _)| k.borrow() == key)
+        self.items.iter().any(|(k, _)| k.borrow() == key)
     }
 
-    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+    /// Appends an item known to be absent from the bucket.
+    pub(crate) fn push(&mut self, hash: u64, key: K, value: V) -> &mut V {
+        self.hashes.push(hash);
+        self.items.push((key, value));
+        &mut self.items.last_mut().unwrap().1
+    }
+
+    pub(crate) fn insert(&mut self, hash: u64, key: K, value: V) -> Option<V> {
         for &mut (ref ekey, ref mut evalue) in self.items.iter_mut() {
             if ekey == &key {
                 return Some(mem::replace(evalue, value));
             }
         }
-        self.items.push((key, value));
+        self.push(hash, key, value);
         None
     }
 
-    pub fn insert_mut(&mut self, key: K, value: V) -> &mut V {
+    /// Inserts the pair, swapping out both the key and the value of an
+    /// equal key already present.
+    pub(crate) fn replace(&mut self, hash: u64, key: K, value: V) -> Option<(K, V)> {
+        match self.items.iter().position(|(k, _)| k == &key) {
+            Some(i) => {
+                self.hashes[i] = hash;
+                Some(mem::replace(&mut self.items[i], (key, value)))
+            }
+            None => {
+                self.push(hash, key, value);
+                None
+            }
+        }
+    }
+
+    pub(crate) fn insert_mut(&mut self, hash: u64, key: K, value: V) -> &mut V {
         if self.contains_key(key.borrow()) {
             *self.get_mut(&key).unwrap() = value;
             self.get_mut(&key).unwrap()
         } else {
-            self.items.push((key, value));
-            &mut self.items.last_mut().unwrap().1
+            self.push(hash, key, value)
         }
     }
 
-    pub fn remove<Q: ?Sized>(&mut self, key: &Q) -> Option<V>
+    /// Returns the value at `key`, inserting `f()` first if it is absent.
+    /// The flag is `true` when a new item was pushed.
+    pub(crate) fn get_or_insert_with<F: FnOnce() -> V>(
+        &mut self,
+        hash: u64,
+        key: K,
+        f: F,
+    ) -> (&mut V, bool) {
+        match self.items.iter().position(|(k, _)| k == &key) {
+            Some(i) => (&mut self.items[i].1, false),
+            None => (self.push(hash, key, f()), true),
+        }
+    }
+
+    /// Position of `key` among the items.
+    pub(crate) fn position<Q>(&self, key: &Q) -> Option<usize>
     where
         K: Borrow<Q>,
-        Q: Eq + Hash,
+        Q: Eq + Hash + ?Sized,
     {
-        let i = self
-            .items
-            .iter()
-            .position(|&(ref k, _)| k.borrow() == key)?;
-        Some(self.items.swap_remove(i).1)
+        self.items.iter().position(|(k, _)| k.borrow() == key)
     }
 
-    pub fn at(&self, idx: usize) -> Option<&(K, V)> {
-        self.items.get(idx)
+    pub(crate) fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let i = self.position(key)?;
+        Some(self.remove_at(i).1)
+    }
+
+    /// Removes the item at `idx`, moving the last item in its place.
+    pub(crate) fn remove_at(&mut self, idx: usize) -> (K, V) {
+        self.hashes.swap_remove(idx);
+        self.items.swap_remove(idx)
+    }
+
+    /// Removes the last item of the bucket.
+    pub(crate) fn pop(&mut self) -> Option<(K, V)> {
+        self.hashes.pop();
+        self.items.pop()
+    }
+
+    /// Moves the items whose cached hash matches `f` to `other`, keeping
+    /// the others in place.
+    pub(crate) fn move_where<F: FnMut(u64) -> bool>(&mut self, other: &mut Bucket<K, V>, mut f: F) {
+        let mut i = 0;
+        while i < self.hashes.len() {
+            let hash = self.hashes[i];
+            if f(hash) {
+                let (key, value) = self.remove_at(i);
+                other.push(hash, key, value);
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    /// Keeps only the items for which `f` holds, returning how many were
+    /// dropped.
+    pub(crate) fn retain<F: FnMut(&K, &mut V) -> bool>(&mut self, mut f: F) -> usize {
+        let before = self.items.len();
+        let mut i = 0;
+        while i < self.items.len() {
+            let (k, v) = &mut self.items[i];
+            if f(k, v) {
+                i += 1;
+            } else {
+                self.items.swap_remove(i);
+                self.hashes.swap_remove(i);
+            }
+        }
+        before - self.items.len()
     }
 
-    pub fn at_mut(&mut self, idx: usize) -> Option<&mut (K, V)> {
-        self.items.get_mut(idx)
+    /// Passes every item by value to `f`, keeping the returned values and
+    /// dropping items mapped to `None`. Returns how many were dropped.
+    pub(crate) fn retain_map<F: FnMut(&K, V) -> Option<V>>(&mut self, mut f: F) -> usize {
+        let before = self.items.len();
+        if before == 0 {
+            return 0;
+        }
+        let items = mem::replace(&mut self.items, Vec::with_capacity(before));
+        let hashes = mem::replace(&mut self.hashes, Vec::with_capacity(before));
+        for (hash, (key, value)) in hashes.into_iter().zip(items) {
+            if let Some(value) = f(&key, value) {
+                self.push(hash, key, value);
+            }
+        }
+        before - self.items.len()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.items.clear();
+        self.hashes.clear();
+    }
+
+    pub fn at(&self, idx: usize) -> Option<&(K, V)> {
+        self.items.get(idx)
     }
 }
 
@@ -0,0 +1,112 @@
+use super::*;
+
+use std::borrow::Borrow;
+use std::cmp::Eq;
+use std::hash::Hash;
+use std::mem;
+
+/// Hash map refusing new keys once it holds `max_len` entries.
+///
+/// Existing keys can still be updated when the map is full. Nothing is
+/// evicted: inserting a new key into a full map hands the value back.
+pub struct CappedMap<K: Eq + Hash, V> {
+    map: HashMap<K, V>,
+    max_len: usize,
+}
+
+impl<K: Eq + Hash, V> CappedMap<K, V> {
+    pub fn new(max_len: usize) -> Self {
+        CappedMap {
+            map: HashMap::with_capacity(max_len),
+            max_len,
+        }
+    }
+
+    pub fn max_len(&self) -> usize {
+        self.max_len
+    }
+
+    /// Inserts the pair, returning the previous value of the key, or
+    /// `Err(value)` if the key is new and the map is full.
+    pub fn insert(&mut self, key: K, value: V) -> Result<Option<V>, V> {
+        if let Some(slot) = self.map.get_mut(&key) {
+            return Ok(Some(mem::replace(slot, value)));
+        }
+        if self.map.len() >= self.max_len {
+            return Err(value);
+        }
+        Ok(self.map.insert(key, value))
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.map.get(key)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.map.contains_key(key)
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.map.remove(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.map.len() >= self.max_len
+    }
+
+    pub fn as_map(&self) -> &HashMap<K, V> {
+        &self.map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::CappedMap;
+
+    #[test]
+    fn rejects_new_keys_when_full() {
+        let mut m = CappedMap::new(3);
+        for i in 0..3 {
+            assert_eq!(m.insert(i, i), Ok(None));
+        }
+        assert!(m.is_full());
+        assert_eq!(m.insert(3, 3), Err(3));
+        assert!(!m.contains_key(&3));
+        assert_eq!(m.insert(1, 10), Ok(Some(1)));
+        assert_eq!(m.get(&1), Some(&10));
+        assert_eq!(m.len(), 3);
+
+        assert_eq!(m.remove(&0), Some(0));
+        assert_eq!(m.insert(3, 3), Ok(None));
+        assert_eq!(m.as_map().len(), 3);
+    }
+
+    #[test]
+    fn keeps_capacity_on_removal() {
+        let mut m = CappedMap::new(100_000);
+        let capacity = m.as_map().capacity();
+        m.insert(1, 1).unwrap();
+        m.remove(&1);
+        assert_eq!(m.as_map().capacity(), capacity);
+    }
+}
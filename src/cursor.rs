@@ -0,0 +1,114 @@
+use super::*;
+
+use std::cmp::Eq;
+use std::hash::Hash;
+
+/// Cursor over the entries of a map, able to remove the entry it is on.
+///
+/// Entries come in bucket order. Removals do not shrink the map, so that
+/// the remaining entries are not moved while iterating.
+pub struct CursorMut<'a, K: Eq + Hash, V, S = DefaultHashBuilder> {
+    map: &'a mut HashMap<K, V, S>,
+    bucket: usize,
+    index: usize,
+    current: Option<(usize, usize)>,
+}
+
+impl<'a, K: Eq + Hash, V, S: BuildHasher> CursorMut<'a, K, V, S> {
+    pub(crate) fn new(map: &'a mut HashMap<K, V, S>) -> Self {
+        CursorMut {
+            map,
+            bucket: 0,
+            index: 0,
+            current: None,
+        }
+    }
+
+    /// Moves to the next entry and returns it, or `None` once every entry
+    /// was visited.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<(&K, &mut V)> {
+        while self.bucket < self.map.buckets.len() {
+            if self.index < self.map.buckets[self.bucket].items.len() {
+                self.current = Some((self.bucket, self.index));
+                let (k, v) = &mut self.map.buckets[self.bucket].items[self.index];
+                self.index += 1;
+                return Some((k, v));
+            }
+            self.bucket += 1;
+            self.index = 0;
+        }
+        self.current = None;
+        None
+    }
+
+    /// Removes the entry last returned by `next`, `None` if there is none
+    /// or it was already removed.
+    pub fn remove_current(&mut self) -> Option<(K, V)> {
+        let (bucket, index) = self.current.take()?;
+        let res = self.map.buckets[bucket].remove_at(index);
+        self.map.unmark_if_empty(bucket);
+        debug_assert!(
+            self.map.num_items > 0,
+            "item count out of sync, see `recount`"
+        );
+        self.map.num_items -= 1;
+        // the last item of the bucket took the removed one's place
+        self.index = index;
+        Some(res)
+    }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher> HashMap<K, V, S> {
+    /// Cursor visiting every entry, see `CursorMut`.
+    pub fn cursor_mut(&mut self) -> CursorMut<'_, K, V, S> {
+        CursorMut::new(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::Colliding;
+    use crate::HashMap;
+
+    #[test]
+    fn remove_every_other_entry() {
+        let mut m: HashMap<u32, u32> = (0..1000).map(|i| (i, i)).collect();
+        let mut cursor = m.cursor_mut();
+        let mut visited = 0;
+        while let Some((_, v)) = cursor.next() {
+            *v += 1;
+            visited += 1;
+            if visited % 2 == 0 {
+                assert!(cursor.remove_current().is_some());
+                assert!(cursor.remove_current().is_none());
+            }
+        }
+        assert_eq!(visited, 1000);
+        assert_eq!(m.len(), 500);
+        assert_eq!(m.recount(), 500);
+        assert!(m.iter().all(|(k, v)| *v == k + 1));
+    }
+
+    #[test]
+    fn remove_within_one_bucket() {
+        let mut m = HashMap::new();
+        for i in 0..6 {
+            m.insert(Colliding(i), i);
+        }
+        let mut cursor = m.cursor_mut();
+        let mut visited = Vec::new();
+        while let Some((k, _)) = cursor.next() {
+            let k = *k;
+            visited.push(k.0);
+            if k.0 % 2 == 0 {
+                assert_eq!(cursor.remove_current(), Some((k, k.0)));
+            }
+        }
+        visited.sort_unstable();
+        assert_eq!(visited, vec![0, 1, 2, 3, 4, 5]);
+        let mut survivors: Vec<u32> = m.keys().map(|k| k.0).collect();
+        survivors.sort_unstable();
+        assert_eq!(survivors, vec![1, 3, 5]);
+    }
+}
@@ -0,0 +1,367 @@
+use super::*;
+
+use std::borrow::Borrow;
+use std::cmp::Eq;
+use std::fmt;
+use std::hash::Hash;
+use std::mem::size_of;
+
+impl<K: Eq + Hash, V, S: BuildHasher> HashMap<K, V, S> {
+    /// Number of other keys stored in the bucket `key` hashes to.
+    pub fn collision_count<Q>(&self, key: &Q) -> usize
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        if self.buckets.is_empty() {
+            return 0;
+        }
+        let idx = self.bucket_index(self.hash_key(key));
+        let bucket = &self.buckets[idx];
+        let len = bucket.items.len();
+        if bucket.contains_key(key) {
+            len - 1
+        } else {
+            len
+        }
+    }
+
+    /// Number of entries stored beyond the first one of their bucket.
+    ///
+    /// This is zero when no two keys share a bucket and `len() - 1` when
+    /// they all do.
+    pub fn total_collisions(&self) -> usize {
+        self.buckets
+            .iter()
+            .map(|bkt| bkt.items.len().saturating_sub(1))
+            .sum()
+    }
+
+    /// Number of entries in each bucket, by bucket index.
+    ///
+    /// The result has one element per bucket, so its length is the current
+    /// bucket count.
+    pub fn bucket_lengths(&self) -> Vec<usize> {
+        self.buckets.iter().map(|bkt| bkt.items.len()).collect()
+    }
+
+    /// Chi-squared statistic of the bucket lengths against a uniform
+    /// spread of the entries, `0.0` for an empty map.
+    ///
+    /// A good hasher gives a value around the bucket count, a much higher
+    /// one means keys cluster in a few buckets.
+    pub fn distribution_chi_squared(&self) -> f64 {
+        if self.num_items == 0 {
+            return 0.0;
+        }
+        let expected = self.num_items as f64 / self.buckets.len() as f64;
+        self.buckets
+            .iter()
+            .map(|bkt| {
+                let diff = bkt.items.len() as f64 - expected;
+                diff * diff / expected
+            })
+            .sum()
+    }
+
+    /// Index of the bucket holding the most entries, with its keys, or
+    /// `None` if the map is empty. Ties go to the lowest index.
+    pub fn longest_bucket(&self) -> Option<(usize, Vec<&K>)> {
+        let (idx, bkt) = self
+            .buckets
+            .iter()
+            .enumerate()
+            .rev()
+            .max_by_key(|(_, bkt)| bkt.items.len())?;
+        if bkt.items.is_empty() {
+            return None;
+        }
+        Some((idx, bkt.items.iter().map(|(k, _)| k).collect()))
+    }
+
+    /// Debug view listing the item and bucket counts, the load factor and
+    /// the longest bucket length along with the entries.
+    pub fn debug_verbose(&self) -> DebugVerbose<'_, K, V, S> {
+        DebugVerbose { map: self }
+    }
+
+    /// Rough number of heap bytes held by the map.
+    ///
+    /// Counts the bucket array and the items and cached hashes allocated in
+    /// every bucket, but not heap memory owned by the keys or values.
+    pub fn approx_heap_bytes(&self) -> usize {
+        let buckets = self.buckets.capacity() * size_of::<Bucket<K, V>>();
+        let items: usize = self
+            .buckets
+            .iter()
+            .map(|bkt| {
+                bkt.items.capacity() * size_of::<(K, V)>()
+                    + bkt.hashes.capacity() * size_of::<u64>()
+            })
+            .sum();
+        buckets + items
+    }
+
+    /// Removes and returns all items of the bucket at `idx`.
+    #[cfg(test)]
+    pub(crate) fn drain_bucket(&mut self, idx: usize) -> Vec<(K, V)> {
+        let bkt = &mut self.buckets[idx];
+        bkt.hashes.clear();
+        let items = std::mem::take(&mut bkt.items);
+        self.unmark_if_empty(idx);
+        self.num_items -= items.len();
+        items
+    }
+
+    /// Iterates on the entries along with the index of their bucket.
+    ///
+    /// Entries come by ascending bucket index, then in the order they are
+    /// stored in their bucket: insertion order, unless some were removed.
+    /// With `DefaultHashBuilder` and a fixed seed, the order only depends
+    /// on the keys inserted, not on the run. Hashers seeded at random, such
+    /// as `RandomState`, change it from one run to the next.
+    pub fn iter_bucket_order(&self) -> impl Iterator<Item = (&K, &V, usize)> {
+        self.buckets
+            .iter()
+            .enumerate()
+            .flat_map(|(idx, bkt)| bkt.items.iter().map(move |(k, v)| (k, v, idx)))
+    }
+
+    /// Iterates on the entries, in the order of `iter_bucket_order`, along
+    /// with the number of items in their bucket.
+    pub fn iter_with_bucket_occupancy(&self) -> impl Iterator<Item = (&K, &V, usize)> {
+        self.iter_bucket_order()
+            .map(move |(k, v, idx)| (k, v, self.buckets[idx].items.len()))
+    }
+
+    /// Indices of the buckets holding at least one entry, ascending.
+    ///
+    /// Along with `bucket_slice`, this lets work be split per non-empty
+    /// bucket.
+    pub fn occupied_bucket_indices(&self) -> impl Iterator<Item = usize> + '_ {
+        self.buckets
+            .iter()
+            .enumerate()
+            .filter(|(_, bkt)| !bkt.items.is_empty())
+            .map(|(idx, _)| idx)
+    }
+
+    /// Entries stored in the bucket at `idx`.
+    ///
+    /// Panics if `idx` is not below the number of buckets.
+    pub fn bucket_slice(&self, idx: usize) -> &[(K, V)] {
+        &self.buckets[idx].items
+    }
+}
+
+/// Debug view of a map's internal state, see `HashMap::debug_verbose`.
+pub struct DebugVerbose<'a, K: Eq + Hash, V, S> {
+    map: &'a HashMap<K, V, S>,
+}
+
+impl<'a, K, V, S> fmt::Debug for DebugVerbose<'a, K, V, S>
+where
+    K: Eq + Hash + fmt::Debug,
+    V: fmt::Debug,
+    S: BuildHasher,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let map = self.map;
+        let max_bucket_len = map.buckets.iter().map(|bkt| bkt.items.len()).max();
+        f.debug_struct("HashMap")
+            .field("num_items", &map.num_items)
+            .field("buckets", &map.buckets.len())
+            .field("load_factor", &map.load_factor())
+            .field("max_bucket_len", &max_bucket_len.unwrap_or(0))
+            .field("entries", &map.entries())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{Colliding, Identity};
+    use crate::HashMap;
+    use std::hash::BuildHasherDefault;
+
+    #[test]
+    fn collisions_in_bucket() {
+        let mut m = HashMap::new();
+        for i in 0..5 {
+            m.insert(Colliding(i), i);
+        }
+        assert_eq!(m.collision_count(&Colliding(0)), 4);
+        assert_eq!(m.collision_count(&Colliding(42)), 5);
+
+        let empty: HashMap<Colliding, u32> = HashMap::new();
+        assert_eq!(empty.collision_count(&Colliding(0)), 0);
+    }
+
+    #[test]
+    fn occupancy_of_clustered_keys() {
+        let mut m = HashMap::new();
+        for i in 0..4 {
+            m.insert(Colliding(i), i);
+        }
+        let entries: Vec<_> = m.iter_with_bucket_occupancy().collect();
+        assert_eq!(entries.len(), 4);
+        assert!(entries.iter().all(|&(_, _, occupancy)| occupancy == 4));
+
+        m.remove(&Colliding(0));
+        assert!(m
+            .iter_with_bucket_occupancy()
+            .all(|(_, _, occupancy)| occupancy == 3));
+    }
+
+    #[test]
+    fn drain_clustered_bucket() {
+        let mut m = HashMap::new();
+        for i in 0..5 {
+            m.insert(Colliding(i), i);
+        }
+        m.insert_mut(Colliding(5), 5);
+        let idx = m.bucket_index(m.hash_key(&Colliding(0)));
+        let mut drained = m.drain_bucket(idx);
+        drained.sort_by_key(|&(_, v)| v);
+        assert_eq!(
+            drained,
+            (0..6).map(|i| (Colliding(i), i)).collect::<Vec<_>>()
+        );
+        assert_eq!(m.len(), 0);
+        assert!(!m.contains_key(&Colliding(3)));
+        assert!(m.drain_bucket(idx).is_empty());
+    }
+
+    #[test]
+    fn total_collisions_gauge() {
+        let mut clustered = HashMap::new();
+        for i in 0..50 {
+            clustered.insert(Colliding(i), i);
+        }
+        assert_eq!(clustered.total_collisions(), clustered.len() - 1);
+
+        let spread: HashMap<u32, u32> = (0..100).map(|i| (i, i)).collect();
+        assert!(spread.total_collisions() < 20);
+        assert_eq!(HashMap::<u32, u32>::new().total_collisions(), 0);
+    }
+
+    #[test]
+    fn heap_bytes_estimate() {
+        let mut m: HashMap<u64, u64> = HashMap::new();
+        assert_eq!(m.approx_heap_bytes(), 0);
+        m.insert(0, 0);
+        let one = m.approx_heap_bytes();
+        assert!(one > 0);
+        for i in 1..100 {
+            m.insert(i, i);
+        }
+        let hundred = m.approx_heap_bytes();
+        assert!(hundred > one);
+        let buckets = m.buckets.len();
+        for i in 100..10_000 {
+            m.insert(i, i);
+        }
+        assert!(m.buckets.len() > buckets);
+        assert!(m.approx_heap_bytes() > hundred);
+        assert!(m.approx_heap_bytes() >= 10_000 * 3 * std::mem::size_of::<u64>());
+    }
+
+    #[test]
+    fn bucket_order_with_clustered_keys() {
+        let mut m = HashMap::with_hasher(BuildHasherDefault::<Identity>::default());
+        for k in [5u64, 0, 1024, 2048] {
+            m.insert(k, k);
+        }
+        assert_eq!(m.buckets.len(), 1024);
+        let entries: Vec<(u64, u64, usize)> = m
+            .iter_bucket_order()
+            .map(|(&k, &v, idx)| (k, v, idx))
+            .collect();
+        assert_eq!(
+            entries,
+            vec![(0, 0, 0), (1024, 1024, 0), (2048, 2048, 0), (5, 5, 5)]
+        );
+
+        let spread: HashMap<u32, u32> = (0..100).map(|i| (i, i)).collect();
+        let indices: Vec<usize> = spread.iter_bucket_order().map(|(_, _, idx)| idx).collect();
+        assert_eq!(indices.len(), 100);
+        assert!(indices.windows(2).all(|w| w[0] <= w[1]));
+        for (k, _, idx) in spread.iter_bucket_order() {
+            assert_eq!(idx, spread.bucket_index(spread.hash_key(k)));
+        }
+    }
+
+    #[test]
+    fn occupied_buckets_of_known_keys() {
+        let mut m = HashMap::with_hasher(BuildHasherDefault::<Identity>::default());
+        for k in [3u64, 17, 500, 1024 + 3] {
+            m.insert(k, k);
+        }
+        assert_eq!(m.buckets.len(), 1024);
+        let indices: Vec<usize> = m.occupied_bucket_indices().collect();
+        assert_eq!(indices, vec![3, 17, 500]);
+        assert_eq!(m.bucket_slice(3), &[(3, 3), (1027, 1027)]);
+        assert_eq!(m.bucket_slice(17), &[(17, 17)]);
+        assert!(m.bucket_slice(4).is_empty());
+        let total: usize = indices.iter().map(|&idx| m.bucket_slice(idx).len()).sum();
+        assert_eq!(total, m.len());
+    }
+
+    #[test]
+    fn longest_bucket_of_forced_collisions() {
+        let mut m = HashMap::with_hasher(BuildHasherDefault::<Identity>::default());
+        assert!(m.longest_bucket().is_none());
+        for k in [7u64, 5, 1024 + 5, 9, 2048 + 5, 1024 + 9] {
+            m.insert(k, ());
+        }
+        let (idx, mut keys) = m.longest_bucket().unwrap();
+        assert_eq!(idx, 5);
+        keys.sort_unstable();
+        assert_eq!(keys, vec![&5, &1029, &2053]);
+    }
+
+    #[test]
+    fn verbose_debug_shows_state() {
+        let mut m = HashMap::new();
+        for i in 0..3 {
+            m.insert(Colliding(i), i);
+        }
+        let dump = format!("{:?}", m.debug_verbose());
+        assert!(dump.contains("num_items: 3"));
+        assert!(dump.contains("buckets: 1024"));
+        assert!(dump.contains(&format!("load_factor: {:?}", 3.0 / 1024.0)));
+        assert!(dump.contains("max_bucket_len: 3"));
+        assert!(dump.contains("Colliding(2): 2"));
+        assert_eq!(format!("{:?}", m), format!("{:?}", m.entries()));
+    }
+
+    #[test]
+    fn bucket_lengths_sum_to_len() {
+        assert!(HashMap::<u32, u32>::new().bucket_lengths().is_empty());
+        let mut m: HashMap<u32, u32> = (0..5000).map(|i| (i, i)).collect();
+        m.remove(&0);
+        let lengths = m.bucket_lengths();
+        assert_eq!(lengths.len(), m.buckets.len());
+        assert_eq!(lengths.iter().sum::<usize>(), m.len());
+    }
+
+    #[test]
+    fn chi_squared_flags_clustering() {
+        assert_eq!(HashMap::<u32, u32>::new().distribution_chi_squared(), 0.0);
+
+        let mut uniform = HashMap::with_hasher(BuildHasherDefault::<Identity>::default());
+        for k in 0..3 * 1024u64 {
+            uniform.insert(k, ());
+        }
+        assert_eq!(uniform.distribution_chi_squared(), 0.0);
+
+        let spread: HashMap<u32, u32> = (0..3000).map(|i| (i, i)).collect();
+        assert!(spread.distribution_chi_squared() < 2.0 * spread.buckets.len() as f64);
+
+        let mut clustered = HashMap::new();
+        for i in 0..3000 {
+            clustered.insert(Colliding(i), i);
+        }
+        assert!(clustered.distribution_chi_squared() > 100.0 * clustered.buckets.len() as f64);
+    }
+}
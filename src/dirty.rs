@@ -0,0 +1,74 @@
+use super::*;
+
+use std::borrow::Borrow;
+use std::cmp::Eq;
+use std::hash::Hash;
+use std::ops::{Deref, DerefMut};
+
+/// Mutable reference to a value of a map, recording whether it was
+/// accessed mutably.
+///
+/// Any mutable dereference marks the guard as dirty, whether or not the
+/// value was actually changed.
+pub struct DirtyGuard<'a, V> {
+    value: &'a mut V,
+    dirty: bool,
+}
+
+impl<'a, V> DirtyGuard<'a, V> {
+    /// Whether the value was dereferenced mutably through this guard.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+}
+
+impl<'a, V> Deref for DirtyGuard<'a, V> {
+    type Target = V;
+    fn deref(&self) -> &V {
+        self.value
+    }
+}
+
+impl<'a, V> DerefMut for DirtyGuard<'a, V> {
+    fn deref_mut(&mut self) -> &mut V {
+        self.dirty = true;
+        self.value
+    }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher> HashMap<K, V, S> {
+    /// Same as `get_mut`, wrapping the value in a guard that tracks
+    /// whether it was written through.
+    pub fn get_mut_tracked<Q>(&mut self, key: &Q) -> Option<DirtyGuard<'_, V>>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let value = self.get_mut(key)?;
+        Some(DirtyGuard {
+            value,
+            dirty: false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::HashMap;
+
+    #[test]
+    fn writes_mark_guard_dirty() {
+        let mut m: HashMap<&str, Vec<u32>> = HashMap::new();
+        m.insert("a", vec![1]);
+
+        let read = m.get_mut_tracked("a").unwrap();
+        assert_eq!(read.len(), 1);
+        assert!(!read.is_dirty());
+
+        let mut written = m.get_mut_tracked("a").unwrap();
+        written.push(2);
+        assert!(written.is_dirty());
+        assert_eq!(m.get("a"), Some(&vec![1, 2]));
+        assert!(m.get_mut_tracked("b").is_none());
+    }
+}
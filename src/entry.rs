@@ -3,38 +3,81 @@ use std::hash::Hash;
 
 use super::*;
 
-pub enum Entry<'a, K: Eq + Hash, V> {
-    Vacant(VacantEntry<'a, K, V>),
-    Occupied(OccupiedEntry<'a, K, V>),
+pub enum Entry<'a, K: Eq + Hash, V, S = DefaultHashBuilder> {
+    Vacant(VacantEntry<'a, K, V, S>),
+    Occupied(OccupiedEntry<'a, K, V, S>),
 }
 
-impl<'a, K: Eq + Hash, V> Entry<'a, K, V> {
-    pub fn new(map: &'a mut HashMap<K, V>, key: K) -> Entry<'a, K, V> {
-        if map.contains_key(&key) {
-            let v = map.get_mut(&key).unwrap();
-            Self::Occupied(OccupiedEntry { key, value: v })
-        } else {
-            Self::Vacant(VacantEntry { key, map })
+impl<'a, K: Eq + Hash, V, S: BuildHasher> Entry<'a, K, V, S> {
+    pub fn new(map: &'a mut HashMap<K, V, S>, key: K) -> Entry<'a, K, V, S> {
+        if map.buckets.is_empty() {
+            return Self::Vacant(VacantEntry { key, map });
+        }
+        let bucket = map.bucket_index(map.hash_key(&key));
+        match map.buckets[bucket].position(&key) {
+            Some(index) => Self::Occupied(OccupiedEntry::new(map, bucket, index)),
+            None => Self::Vacant(VacantEntry { key, map }),
         }
     }
+
     pub fn or_insert(self, val: V) -> &'a mut V {
         match self {
             Entry::Vacant(ventry) => ventry.map.insert_mut(ventry.key, val),
-            Entry::Occupied(oentry) => oentry.value,
+            Entry::Occupied(oentry) => oentry.into_mut(),
+        }
+    }
+
+    /// Same as `or_insert`, also returning `true` if `default` was
+    /// inserted and `false` if the entry was already occupied.
+    pub fn or_insert_reporting(self, default: V) -> (&'a mut V, bool) {
+        match self {
+            Entry::Vacant(ventry) => (ventry.insert(default), true),
+            Entry::Occupied(oentry) => (oentry.into_mut(), false),
         }
     }
 
     pub fn or_insert_with<F: FnOnce() -> V>(self, f: F) -> &'a mut V {
         match self {
             Entry::Vacant(ventry) => ventry.map.insert_mut(ventry.key, f()),
-            Entry::Occupied(oentry) => oentry.value,
+            Entry::Occupied(oentry) => oentry.into_mut(),
+        }
+    }
+
+    /// Same as `or_insert_with` for a fallible `f`. On error, nothing is
+    /// inserted and the map is left untouched.
+    pub fn or_try_insert_with<F, E>(self, f: F) -> Result<&'a mut V, E>
+    where
+        F: FnOnce() -> Result<V, E>,
+    {
+        match self {
+            Entry::Vacant(ventry) => {
+                let value = f()?;
+                Ok(ventry.map.insert_mut(ventry.key, value))
+            }
+            Entry::Occupied(oentry) => Ok(oentry.into_mut()),
         }
     }
 
     pub fn key(&self) -> &K {
         match self {
             Entry::Vacant(ventry) => &ventry.key,
-            Entry::Occupied(oentry) => &oentry.key,
+            Entry::Occupied(oentry) => oentry.key(),
+        }
+    }
+
+    /// Returns the entry if it is occupied.
+    pub fn occupied(self) -> Option<OccupiedEntry<'a, K, V, S>> {
+        match self {
+            Entry::Vacant(_) => None,
+            Entry::Occupied(oentry) => Some(oentry),
+        }
+    }
+
+    /// Returns the entry if it is vacant.
+    pub fn vacant(self) -> Option<VacantEntry<'a, K, V, S>> {
+        match self {
+            Entry::Vacant(ventry) => Some(ventry),
+            Entry::Occupied(_) => None,
         }
     }
 
@@ -44,33 +87,111 @@ impl<'a, K: Eq + Hash, V> Entry<'a, K, V> {
     {
         match self {
             Entry::Vacant(ventry) => Entry::Vacant(ventry),
-            Entry::Occupied(oentry) => {
-                f(oentry.value);
+            Entry::Occupied(mut oentry) => {
+                f(oentry.get_mut());
                 Entry::Occupied(oentry)
             }
         }
     }
+
+    /// Applies `f` to the value of an occupied entry, removing the entry
+    /// if `f` returns `false`. Vacant entries are returned as they are.
+    pub fn modify_or_remove<F>(self, f: F) -> Self
+    where
+        F: FnOnce(&mut V) -> bool,
+    {
+        match self {
+            Entry::Vacant(ventry) => Entry::Vacant(ventry),
+            Entry::Occupied(mut oentry) => {
+                if f(oentry.get_mut()) {
+                    return Entry::Occupied(oentry);
+                }
+                let OccupiedEntry { map, bucket, index } = oentry;
+                let (key, _) = map.remove_at(bucket, index);
+                Entry::Vacant(VacantEntry { map, key })
+            }
+        }
+    }
 }
 
-pub struct VacantEntry<'a, K: Eq + Hash, V> {
-    map: &'a mut HashMap<K, V>,
+pub struct VacantEntry<'a, K: Eq + Hash, V, S = DefaultHashBuilder> {
+    map: &'a mut HashMap<K, V, S>,
     key: K,
 }
 
-impl<'a, K: Eq + Hash, V> VacantEntry<'a, K, V> {
+impl<'a, K: Eq + Hash, V, S: BuildHasher> VacantEntry<'a, K, V, S> {
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// The key to be inserted, which can still be changed as it is only
+    /// hashed on insertion.
+    ///
+    /// If the key is changed to one already present, both `insert` and
+    /// `insert_with` replace the value of the existing entry.
+    pub fn key_mut(&mut self) -> &mut K {
+        &mut self.key
+    }
+
     pub fn insert(self, value: V) -> &'a mut V {
         self.map.insert_mut(self.key, value)
     }
+
+    /// Inserts the value returned by `f`, which is only called here.
+    ///
+    /// The value is built when pushed into its bucket, though it may still
+    /// be moved on the way.
+    pub fn insert_with<F: FnOnce() -> V>(self, f: F) -> &'a mut V {
+        let mut f = Some(f);
+        let (value, inserted) = self
+            .map
+            .get_or_insert_with_status(self.key, || (f.take().unwrap())());
+        if !inserted {
+            // the key was changed through `key_mut` to a present one
+            *value = (f.take().unwrap())();
+        }
+        value
+    }
 }
 
-pub struct OccupiedEntry<'a, K, V> {
-    key: K,
-    value: &'a mut V,
+/// Entry of a key present in the map, located by its bucket and its
+/// position in the bucket.
+pub struct OccupiedEntry<'a, K: Eq + Hash, V, S = DefaultHashBuilder> {
+    map: &'a mut HashMap<K, V, S>,
+    bucket: usize,
+    index: usize,
+}
+
+impl<'a, K: Eq + Hash, V, S: BuildHasher> OccupiedEntry<'a, K, V, S> {
+    pub(crate) fn new(map: &'a mut HashMap<K, V, S>, bucket: usize, index: usize) -> Self {
+        OccupiedEntry { map, bucket, index }
+    }
+
+    /// The key stored in the map.
+    pub fn key(&self) -> &K {
+        &self.map.buckets[self.bucket].items[self.index].0
+    }
+
+    pub fn get(&self) -> &V {
+        &self.map.buckets[self.bucket].items[self.index].1
+    }
+
+    pub fn get_mut(&mut self) -> &mut V {
+        &mut self.map.buckets[self.bucket].items[self.index].1
+    }
+
+    pub fn into_mut(self) -> &'a mut V {
+        &mut self.map.buckets[self.bucket].items[self.index].1
+    }
+
+    /// Removes the entry from the map, returning its value.
+    pub fn remove(self) -> V {
+        self.map.remove_at(self.bucket, self.index).1
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::Bucket;
     use crate::Entry;
     use crate::HashMap;
 
@@ -110,4 +231,150 @@ mod tests {
         map.entry("poneyland").and_modify(|e| *e += 1).or_insert(42);
         assert_eq!(map["poneyland"], 43);
     }
+
+    #[test]
+    fn occupied_or_vacant() {
+        let mut map: HashMap<&str, u32> = HashMap::new();
+        map.insert("present", 1);
+
+        assert!(map.entry("present").vacant().is_none());
+        if let Some(mut o) = map.entry("present").occupied() {
+            assert_eq!(o.key(), &"present");
+            *o.get_mut() += 1;
+        } else {
+            unreachable!();
+        }
+        assert_eq!(map["present"], 2);
+
+        assert!(map.entry("absent").occupied().is_none());
+        let v = map.entry("absent").vacant().unwrap();
+        assert_eq!(v.key(), &"absent");
+        v.insert(3);
+        assert_eq!(map["absent"], 3);
+    }
+
+    #[test]
+    fn vacant_insert_with() {
+        let mut map: HashMap<u32, [u8; 64]> = HashMap::new();
+        let mut calls = 0;
+        if let Entry::Vacant(v) = map.entry(1) {
+            let value = v.insert_with(|| {
+                calls += 1;
+                [0; 64]
+            });
+            value[3] = 7;
+        }
+        assert_eq!(calls, 1);
+        assert_eq!(map[&1][3], 7);
+    }
+
+    #[test]
+    fn fallible_insert() {
+        let mut map: HashMap<&str, u32> = HashMap::new();
+        let res: Result<&mut u32, String> =
+            map.entry("bad").or_try_insert_with(|| Err("nope".into()));
+        assert_eq!(res, Err("nope".to_string()));
+        assert!(map.is_empty());
+        assert!(!map.contains_key("bad"));
+
+        let res: Result<&mut u32, ()> = map.entry("good").or_try_insert_with(|| Ok(1));
+        *res.unwrap() += 1;
+        assert_eq!(map["good"], 2);
+        assert_eq!(map.len(), 1);
+
+        let res: Result<&mut u32, ()> = map.entry("good").or_try_insert_with(|| Err(()));
+        assert_eq!(res, Ok(&mut 2));
+        let res: Result<&mut u32, ()> = map.entry("other").or_try_insert_with(|| Err(()));
+        assert!(res.is_err());
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    fn decrement_to_zero_removes() {
+        let mut counts: HashMap<&str, u32> = HashMap::new();
+        counts.insert("a", 2);
+        counts.insert("b", 1);
+        let decrement = |v: &mut u32| {
+            *v -= 1;
+            *v > 0
+        };
+        assert!(counts
+            .entry("a")
+            .modify_or_remove(decrement)
+            .occupied()
+            .is_some());
+        assert_eq!(counts.get("a"), Some(&1));
+        let entry = counts.entry("a").modify_or_remove(decrement);
+        assert_eq!(entry.key(), &"a");
+        assert!(entry.vacant().is_some());
+        assert!(!counts.contains_key("a"));
+        assert_eq!(counts.len(), 1);
+        assert!(counts
+            .entry("z")
+            .modify_or_remove(|_| unreachable!())
+            .vacant()
+            .is_some());
+        assert_eq!(counts.len(), 1);
+        assert_eq!(counts.get("b"), Some(&1));
+    }
+
+    #[test]
+    fn occupied_entry_by_borrowed_key() {
+        let mut m: HashMap<String, u32> = HashMap::new();
+        m.insert("counter".to_string(), 1);
+        m.insert("other".to_string(), 5);
+        assert!(m.get_entry_mut("missing").is_none());
+
+        let mut entry = m.get_entry_mut("counter").unwrap();
+        assert_eq!(entry.key(), "counter");
+        *entry.get_mut() += 1;
+        assert_eq!(entry.get(), &2);
+        assert_eq!(entry.remove(), 2);
+        assert!(!m.contains_key("counter"));
+        assert_eq!(m.len(), 1);
+        assert!(HashMap::<String, u32>::new()
+            .get_entry_mut("counter")
+            .is_none());
+    }
+
+    #[test]
+    fn normalize_vacant_key() {
+        let mut m: HashMap<String, u32> = HashMap::new();
+        if let Some(mut vacant) = m.entry("  Hello ".to_string()).vacant() {
+            let key = vacant.key_mut();
+            *key = key.trim().to_lowercase();
+            vacant.insert(1);
+        }
+        assert_eq!(m.get("hello"), Some(&1));
+        assert!(!m.contains_key("  Hello "));
+        assert_eq!(m.len(), 1);
+    }
+
+    #[test]
+    fn or_insert_reporting() {
+        let mut m: HashMap<&str, u32> = HashMap::new();
+        let (value, inserted) = m.entry("a").or_insert_reporting(1);
+        assert!(inserted);
+        *value += 1;
+        let (value, inserted) = m.entry("a").or_insert_reporting(10);
+        assert!(!inserted);
+        assert_eq!(*value, 2);
+        assert_eq!(m.len(), 1);
+    }
+
+    #[test]
+    fn vacant_key_changed_to_present_one() {
+        let mut m: HashMap<String, u32> = HashMap::new();
+        m.insert("taken".to_string(), 1);
+        if let Some(mut vacant) = m.entry("other".to_string()).vacant() {
+            *vacant.key_mut() = "taken".to_string();
+            assert_eq!(*vacant.insert(2), 2);
+        }
+        if let Some(mut vacant) = m.entry("other".to_string()).vacant() {
+            *vacant.key_mut() = "taken".to_string();
+            assert_eq!(*vacant.insert_with(|| 3), 3);
+        }
+        assert_eq!(m.get("taken"), Some(&3));
+        assert_eq!(m.len(), 1);
+    }
 }
@@ -5,7 +5,12 @@ use std::borrow::Borrow;
 use std::cmp::Eq;
 use std::hash::Hash;
 
-impl<'a, K: Eq + Hash + Borrow<Q>, V, Q: Eq + Hash + ?Sized> Index<&'a Q> for HashMap<K, V> {
+impl<'a, K, V, Q, S> Index<&'a Q> for HashMap<K, V, S>
+where
+    K: Eq + Hash + Borrow<Q>,
+    Q: Eq + Hash + ?Sized,
+    S: BuildHasher,
+{
     type Output = V;
     fn index(&self, index: &'a Q) -> &Self::Output {
         self.get(index).unwrap()
@@ -1,21 +1,66 @@
 use super::*;
 
 use std::cmp::Eq;
+use std::fmt;
 use std::hash::Hash;
 
+/// Iterator on the entries of a hash map.
+///
+/// Its `Debug` output lists the entries not yielded yet.
+pub struct Entries<'a, K: Eq + Hash, V> {
+    map_iter: HashMapIterator<'a, K, V>,
+}
+
+impl<'a, K: Eq + Hash, V> Entries<'a, K, V> {
+    pub fn new<S>(map: &'a HashMap<K, V, S>) -> Self {
+        Entries {
+            map_iter: map.into_iter(),
+        }
+    }
+}
+
+impl<'a, K: Eq + Hash, V> Clone for Entries<'a, K, V> {
+    fn clone(&self) -> Self {
+        Entries {
+            map_iter: self.map_iter.clone(),
+        }
+    }
+}
+
+impl<'a, K: Eq + Hash, V> Iterator for Entries<'a, K, V> {
+    type Item = (&'a K, &'a V);
+    fn next(&mut self) -> Option<Self::Item> {
+        self.map_iter.next()
+    }
+}
+
+impl<'a, K: Eq + Hash + fmt::Debug, V: fmt::Debug> fmt::Debug for Entries<'a, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.map_iter.clone()).finish()
+    }
+}
+
 /// Iterator on the keys of a hash map.
 pub struct Keys<'a, K: Eq + Hash, V> {
     map_iter: HashMapIterator<'a, K, V>,
 }
 
 impl<'a, K: Eq + Hash, V> Keys<'a, K, V> {
-    pub fn new(map: &'a HashMap<K, V>) -> Self {
+    pub fn new<S>(map: &'a HashMap<K, V, S>) -> Self {
         Keys {
             map_iter: map.into_iter(),
         }
     }
 }
 
+impl<'a, K: Eq + Hash, V> Clone for Keys<'a, K, V> {
+    fn clone(&self) -> Self {
+        Keys {
+            map_iter: self.map_iter.clone(),
+        }
+    }
+}
+
 impl<'a, K: Eq + Hash, V> Iterator for Keys<'a, K, V> {
     type Item = &'a K;
     fn next(&mut self) -> Option<Self::Item> {
@@ -30,13 +75,21 @@ pub struct Values<'a, K: Eq + Hash, V> {
 }
 
 impl<'a, K: Eq + Hash, V> Values<'a, K, V> {
-    pub fn new(map: &'a HashMap<K, V>) -> Self {
+    pub fn new<S>(map: &'a HashMap<K, V, S>) -> Self {
         Values {
             map_iter: map.into_iter(),
         }
     }
 }
 
+impl<'a, K: Eq + Hash, V> Clone for Values<'a, K, V> {
+    fn clone(&self) -> Self {
+        Values {
+            map_iter: self.map_iter.clone(),
+        }
+    }
+}
+
 impl<'a, K: Eq + Hash, V> Iterator for Values<'a, K, V> {
     type Item = &'a V;
     fn next(&mut self) -> Option<Self::Item> {
@@ -44,3 +97,73 @@ impl<'a, K: Eq + Hash, V> Iterator for Values<'a, K, V> {
         Some(v)
     }
 }
+
+/// Iterator on mutable references to the values of a hash map.
+pub struct ValuesMut<'a, K: Eq + Hash, V> {
+    buckets: std::slice::IterMut<'a, Bucket<K, V>>,
+    items: std::slice::IterMut<'a, (K, V)>,
+}
+
+impl<'a, K: Eq + Hash, V> ValuesMut<'a, K, V> {
+    pub fn new<S>(map: &'a mut HashMap<K, V, S>) -> Self {
+        ValuesMut {
+            buckets: map.buckets.iter_mut(),
+            items: [].iter_mut(),
+        }
+    }
+}
+
+impl<'a, K: Eq + Hash, V> Iterator for ValuesMut<'a, K, V> {
+    type Item = &'a mut V;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((_, v)) = self.items.next() {
+                return Some(v);
+            }
+            let bkt = self.buckets.find(|bkt| !bkt.items.is_empty())?;
+            self.items = bkt.items.iter_mut();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::HashMap;
+
+    #[test]
+    fn cloned_keys_restart() {
+        let m: HashMap<u32, u32> = (0..10).map(|i| (i, i * i)).collect();
+        let keys = m.keys();
+        let mut advanced = keys.clone();
+        advanced.next();
+        advanced.next();
+        assert_eq!(advanced.count(), 8);
+        let mut all: Vec<u32> = keys.cloned().collect();
+        all.sort();
+        assert_eq!(all, (0..10).collect::<Vec<_>>());
+
+        let values = m.values();
+        let total: u32 = values.clone().sum();
+        assert_eq!(total, values.sum());
+    }
+
+    #[test]
+    fn entries_debug_shows_remaining() {
+        let m: HashMap<u32, char> = [(1, 'a'), (2, 'b'), (3, 'c')].iter().cloned().collect();
+        let mut pairs: Vec<(u32, char)> = m.entries().map(|(&k, &v)| (k, v)).collect();
+        pairs.sort();
+        assert_eq!(pairs, vec![(1, 'a'), (2, 'b'), (3, 'c')]);
+        assert_eq!(m.iter().count(), 3);
+
+        let mut entries = m.entries();
+        let (first, _) = entries.next().unwrap();
+        let remaining = format!("{:?}", entries);
+        assert!(!remaining.contains(&format!("{}: ", first)));
+        for k in [1, 2, 3].iter().filter(|&k| k != first) {
+            assert!(remaining.contains(&format!("{}: ", k)));
+        }
+        entries.next();
+        entries.next();
+        assert_eq!(format!("{:?}", entries), "{}");
+    }
+}
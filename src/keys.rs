@@ -0,0 +1,76 @@
+//! Key wrappers customizing how keys are compared.
+
+use std::hash::Hash;
+use std::hash::Hasher;
+
+/// String key compared and hashed without regard to case.
+///
+/// `HashMap<CaseInsensitive, V>` finds `"Hello"` when queried with `"HELLO"`.
+/// The original spelling of the key is kept.
+#[derive(Clone, Debug)]
+pub struct CaseInsensitive(pub String);
+
+impl CaseInsensitive {
+    pub fn new(key: &str) -> Self {
+        CaseInsensitive(key.to_string())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    fn folded(&self) -> impl Iterator<Item = char> + '_ {
+        self.0.chars().flat_map(char::to_lowercase)
+    }
+}
+
+impl PartialEq for CaseInsensitive {
+    fn eq(&self, other: &Self) -> bool {
+        self.folded().eq(other.folded())
+    }
+}
+
+impl Eq for CaseInsensitive {}
+
+impl Hash for CaseInsensitive {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for c in self.folded() {
+            c.hash(state);
+        }
+        // terminate like `str` does, so that tuples of keys stay distinct
+        state.write_u8(0xff);
+    }
+}
+
+impl From<&str> for CaseInsensitive {
+    fn from(key: &str) -> Self {
+        CaseInsensitive::new(key)
+    }
+}
+
+impl From<String> for CaseInsensitive {
+    fn from(key: String) -> Self {
+        CaseInsensitive(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CaseInsensitive;
+    use crate::HashMap;
+
+    #[test]
+    fn case_insensitive_lookup() {
+        let mut m: HashMap<CaseInsensitive, u32> = HashMap::new();
+        m.insert("Hello".into(), 1);
+
+        assert_eq!(m.get(&CaseInsensitive::new("HELLO")), Some(&1));
+        assert!(m.contains_key(&"hello".into()));
+        assert!(!m.contains_key(&"hullo".into()));
+        assert_eq!(m[&CaseInsensitive::new("hElLo")], 1);
+
+        assert_eq!(m.insert("HELLO".into(), 2), Some(1));
+        assert_eq!(m.len(), 1);
+        assert_eq!(m.keys().next().unwrap().as_str(), "Hello");
+    }
+}
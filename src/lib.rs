@@ -1,266 +1,2703 @@
 use std::borrow::Borrow;
 use std::cmp::Eq;
+use std::cmp::Ordering;
 use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::collections::TryReserveError;
+use std::convert::Infallible;
+use std::fmt;
+use std::hash::BuildHasher;
+use std::hash::BuildHasherDefault;
 use std::hash::Hash;
 use std::hash::Hasher;
 use std::iter::FromIterator;
 use std::mem;
+use std::ops::AddAssign;
+use std::ops::MulAssign;
+use std::ops::Range;
 
 mod bucket;
+mod capped;
+mod cursor;
+mod diagnostics;
+mod dirty;
 mod entry;
 mod indexing;
 mod key_values;
+pub mod keys;
+mod maintenance;
+#[cfg(feature = "rayon")]
+mod par;
+#[cfg(feature = "rand")]
+mod shuffle;
+mod snapshot;
+#[cfg(test)]
+mod testing;
+mod value_indexed;
+
+pub use bucket::Bucket;
+pub use capped::*;
+pub use cursor::*;
+pub use diagnostics::DebugVerbose;
+pub use dirty::*;
+pub use entry::*;
+pub use key_values::*;
+pub use maintenance::MaintenanceOpts;
+pub use snapshot::*;
+pub use value_indexed::*;
+
+/// Average number of items per bucket the map tolerates before growing.
+const MAX_LOAD_FACTOR: usize = 3;
+
+/// Removals halve the bucket array once the load factor drops below
+/// `1 / MIN_LOAD_INVERSE`.
+///
+/// Growing doubles the buckets and leaves a load factor above 1.5, while
+/// shrinking leaves one below 0.5, so neither can immediately trigger the
+/// other.
+const MIN_LOAD_INVERSE: usize = 4;
+
+/// Number of buckets allocated on the first insertion into an empty map,
+/// below which removals never shrink it.
+const DEFAULT_BUCKETS: usize = 1024;
+
+/// Smallest bucket count allocated when sizing a map explicitly.
+const MIN_BUCKETS: usize = 8;
+
+/// Number of buckets `HashMap::with_capacity(items)` allocates.
+///
+/// This is the smallest power of two, and at least `MIN_BUCKETS`, that
+/// holds `items` elements without exceeding the load factor. Zero items
+/// allocate no bucket at all.
+pub fn capacity_for(items: usize) -> usize {
+    if items == 0 {
+        return 0;
+    }
+    let needed = items.div_ceil(MAX_LOAD_FACTOR);
+    needed.max(MIN_BUCKETS).next_power_of_two()
+}
+
+/// Number of buckets a map with `bucket_count` buckets grows to.
+///
+/// `None` when doubling would overflow `usize`, in which case the map keeps
+/// its buckets and only gets more loaded.
+fn grown_size(bucket_count: usize) -> Option<usize> {
+    match bucket_count {
+        0 => Some(DEFAULT_BUCKETS),
+        n => n.checked_mul(2),
+    }
+}
 
-use bucket::*;
-use entry::*;
-use key_values::*;
+/// Index of the bucket holding `hash` among `bucket_count` buckets.
+///
+/// Bucket counts are always powers of two, so the index is given by the low
+/// bits of the hash. Truncating the hash to `usize` keeps those bits, which
+/// makes the result identical on 32 and 64-bit targets.
+fn index_for(hash: u64, bucket_count: usize) -> usize {
+    debug_assert!(bucket_count.is_power_of_two());
+    (hash as usize) & (bucket_count - 1)
+}
+
+/// Hasher builder used by maps that do not pick one.
+pub type DefaultHashBuilder = BuildHasherDefault<DefaultHasher>;
 
 /// Associative data structure
-pub struct HashMap<K: Eq + Hash, V> {
+///
+/// Keys are hashed with hashers built by `S`.
+pub struct HashMap<K: Eq + Hash, V, S = DefaultHashBuilder> {
     buckets: Vec<Bucket<K, V>>,
     num_items: usize,
+    hash_builder: S,
+    /// Written to every hasher before the key, see `with_seed`.
+    seed: u64,
+    /// Bucket count set by explicit sizing, such as `with_capacity`, under
+    /// which removals do not shrink the map.
+    min_buckets: usize,
+    /// Bit `i % 64` of word `i / 64` is set for every non-empty bucket `i`,
+    /// so that iterating skips empty buckets 64 at a time.
+    occupied: Vec<u64>,
 }
 
-impl<K: Eq + Hash, V> Default for HashMap<K, V> {
+impl<K: Eq + Hash, V, S: Default> Default for HashMap<K, V, S> {
     fn default() -> Self {
         HashMap {
             buckets: Vec::new(),
             num_items: 0,
+            hash_builder: S::default(),
+            seed: 0,
+            min_buckets: 0,
+            occupied: Vec::new(),
         }
     }
 }
 
 impl<K: Hash + Eq, V> HashMap<K, V> {
+    /// Creates an empty map.
+    ///
+    /// Nothing is allocated until the first insertion.
     pub fn new() -> Self {
         Self::default()
     }
 
-    pub fn get<Q: ?Sized>(&self, key: &Q) -> Option<&V>
+    /// Creates a map able to hold `capacity` items without resizing.
+    ///
+    /// Removals do not shrink the map below that size, only `shrink_to`
+    /// and `shrink_to_fit` do.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_hasher(capacity, DefaultHashBuilder::default())
+    }
+
+    /// Creates an empty map mixing `seed` into the hash of every key.
+    ///
+    /// Maps with different seeds spread the same keys differently, so
+    /// collisions cannot be forced without knowing the seed.
+    pub fn with_seed(seed: u64) -> Self {
+        let mut map = Self::new();
+        map.seed = seed;
+        map
+    }
+
+    /// Creates a map holding only `key` and `value`, with the smallest
+    /// bucket array `with_capacity` allocates.
+    pub fn singleton(key: K, value: V) -> Self {
+        let mut map = Self::with_capacity(1);
+        map.insert(key, value);
+        map
+    }
+
+    /// Builds a map from `keys`, computing each value with `f`.
+    pub fn from_keys<I, F>(keys: I, mut f: F) -> Self
     where
-        K: Borrow<Q>,
-        Q: Eq + Hash,
+        I: IntoIterator<Item = K>,
+        F: FnMut(&K) -> V,
+    {
+        let mut map = Self::new();
+        for key in keys {
+            let value = f(&key);
+            map.insert(key, value);
+        }
+        map
+    }
+
+    /// Collects the pairs of `iter` into a map, replacing the value of a
+    /// repeated key with `resolve(existing, incoming)`.
+    pub fn from_iter_with<I, F>(iter: I, mut resolve: F) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        F: FnMut(V, V) -> V,
+    {
+        let mut map = Self::new();
+        for (key, value) in iter {
+            map.grow_if_needed();
+            let hash = map.hash_key(&key);
+            let idx = map.bucket_index(hash);
+            map.mark_occupied(idx);
+            let bucket = &mut map.buckets[idx];
+            match bucket.position(&key) {
+                Some(i) => {
+                    let (key, existing) = bucket.remove_at(i);
+                    bucket.push(hash, key, resolve(existing, value));
+                }
+                None => {
+                    bucket.push(hash, key, value);
+                    map.num_items += 1;
+                }
+            }
+        }
+        map
+    }
+
+    /// Builds a map pairing `keys[i]` with `values[i]`. The last value of a
+    /// repeated key is kept.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the slices have different lengths.
+    pub fn from_slices(keys: &[K], values: &[V]) -> Self
+    where
+        K: Clone,
+        V: Clone,
     {
-        let mut hasher = DefaultHasher::new();
+        assert_eq!(
+            keys.len(),
+            values.len(),
+            "keys and values have different lengths"
+        );
+        let mut map = Self::with_capacity(keys.len());
+        for (key, value) in keys.iter().zip(values) {
+            map.insert(key.clone(), value.clone());
+        }
+        map
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher> HashMap<K, V, S> {
+    /// Creates an empty map hashing keys with `hash_builder`.
+    pub fn with_hasher(hash_builder: S) -> Self {
+        HashMap {
+            buckets: Vec::new(),
+            num_items: 0,
+            hash_builder,
+            seed: 0,
+            min_buckets: 0,
+            occupied: Vec::new(),
+        }
+    }
+
+    /// Creates a map hashing keys with `hash_builder`, able to hold
+    /// `capacity` items without resizing.
+    pub fn with_capacity_and_hasher(capacity: usize, hash_builder: S) -> Self {
+        let mut buckets = Vec::new();
+        buckets.resize_with(capacity_for(capacity), Bucket::new);
+        HashMap {
+            min_buckets: buckets.len(),
+            occupied: vec![0; buckets.len().div_ceil(64)],
+            buckets,
+            num_items: 0,
+            hash_builder,
+            seed: 0,
+        }
+    }
+
+    /// Builder of the hashers used on keys.
+    pub fn hasher(&self) -> &S {
+        &self.hash_builder
+    }
+
+    /// Consumes the map, moving every entry into a map hashing keys with
+    /// `hash_builder`.
+    ///
+    /// Cached hashes are meaningless to the new builder, so every key is
+    /// hashed again.
+    pub fn into_hasher<S2: BuildHasher>(self, hash_builder: S2) -> HashMap<K, V, S2> {
+        let mut map = HashMap::with_capacity_and_hasher(self.num_items, hash_builder);
+        map.num_items = self.num_items;
+        map.seed = self.seed;
+        map.min_buckets = self.min_buckets;
+        for (key, value) in self.into_items() {
+            let hash = map.hash_key(&key);
+            let idx = map.bucket_index(hash);
+            map.mark_occupied(idx);
+            map.buckets[idx].push(hash, key, value);
+        }
+        map
+    }
+
+    /// Number of items the map can hold before it resizes.
+    pub fn capacity(&self) -> usize {
+        self.buckets.len() * MAX_LOAD_FACTOR
+    }
+
+    /// Hash of `key`, shared by every operation locating a bucket.
+    fn hash_key<Q: Hash + ?Sized>(&self, key: &Q) -> u64 {
+        let mut hasher = self.hash_builder.build_hasher();
+        hasher.write_u64(self.seed);
         key.hash(&mut hasher);
-        let idx = (hasher.finish() % self.buckets.len() as u64) as usize;
+        hasher.finish()
+    }
+
+    fn bucket_index(&self, hash: u64) -> usize {
+        index_for(hash, self.buckets.len())
+    }
+
+    /// Bucket index of `key` and its position in the bucket, if present.
+    fn locate<Q>(&self, key: &Q) -> Option<(usize, usize)>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        if self.buckets.is_empty() {
+            return None;
+        }
+        let bucket = self.bucket_index(self.hash_key(key));
+        Some((bucket, self.buckets[bucket].position(key)?))
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        if self.buckets.is_empty() {
+            return None;
+        }
+        let idx = self.bucket_index(self.hash_key(key));
         self.buckets[idx].get(key.borrow())
     }
 
-    pub fn get_mut<Q: ?Sized>(&mut self, key: &Q) -> Option<&mut V>
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
     where
         K: Borrow<Q>,
-        Q: Eq + Hash,
+        Q: Eq + Hash + ?Sized,
     {
-        let mut hasher = DefaultHasher::new();
-        key.hash(&mut hasher);
-        let idx = (hasher.finish() % self.buckets.len() as u64) as usize;
+        if self.buckets.is_empty() {
+            return None;
+        }
+        let idx = self.bucket_index(self.hash_key(key));
         self.buckets[idx].get_mut(key.borrow())
     }
 
-    pub fn contains_key<Q: ?Sized>(&self, key: &Q) -> bool
+    /// Clone of the value at `key`, or `V::default()` if it is absent.
+    ///
+    /// The map is left untouched either way.
+    pub fn get_cloned_or_default<Q>(&self, key: &Q) -> V
     where
         K: Borrow<Q>,
-        Q: Eq + Hash,
+        Q: Eq + Hash + ?Sized,
+        V: Default + Clone,
     {
-        if self.num_items == 0 {
-            return false;
+        self.get(key).cloned().unwrap_or_default()
+    }
+
+    /// Looks up every key yielded by `keys`, in order.
+    pub fn get_all<'a, Q, I>(&'a self, keys: I) -> Vec<Option<&'a V>>
+    where
+        I: IntoIterator<Item = &'a Q>,
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized + 'a,
+    {
+        keys.into_iter().map(|key| self.get(key)).collect()
+    }
+
+    /// Whether every key yielded by `keys` is present, stopping at the
+    /// first missing one.
+    pub fn contains_all<'a, Q, I>(&self, keys: I) -> bool
+    where
+        I: IntoIterator<Item = &'a Q>,
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized + 'a,
+    {
+        keys.into_iter().all(|key| self.contains_key(key))
+    }
+
+    /// Whether any key yielded by `keys` is present, stopping at the first
+    /// one found.
+    pub fn contains_any<'a, Q, I>(&self, keys: I) -> bool
+    where
+        I: IntoIterator<Item = &'a Q>,
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized + 'a,
+    {
+        keys.into_iter().any(|key| self.contains_key(key))
+    }
+
+    /// Mutable references to the values of `keys`, in order, `None` for
+    /// absent keys.
+    ///
+    /// Absent keys are not an error, so that one missing key does not hold
+    /// back the others: `DuplicateKeyError` only describes repeated keys.
+    ///
+    /// Fails without borrowing anything if a key is requested twice. Keys
+    /// are sorted by hash to find repeats, so only keys with equal hashes
+    /// are compared.
+    pub fn get_mut_many<'a, Q>(
+        &'a mut self,
+        keys: &[&Q],
+    ) -> Result<Vec<Option<&'a mut V>>, DuplicateKeyError>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let hashes: Vec<u64> = keys.iter().map(|key| self.hash_key(*key)).collect();
+        let mut by_hash: Vec<usize> = (0..keys.len()).collect();
+        by_hash.sort_unstable_by_key(|&slot| (hashes[slot], slot));
+        let duplicate = by_hash
+            .iter()
+            .enumerate()
+            .flat_map(|(i, &first)| {
+                let (hashes, by_hash) = (&hashes, &by_hash);
+                by_hash[i + 1..]
+                    .iter()
+                    .take_while(move |&&second| hashes[second] == hashes[first])
+                    .filter(move |&&second| keys[first] == keys[second])
+                    .map(move |&second| (first, second))
+            })
+            .min();
+        if let Some((first, second)) = duplicate {
+            return Err(DuplicateKeyError { first, second });
         }
-        let mut hasher = DefaultHasher::new();
-        key.hash(&mut hasher);
-        let idx = (hasher.finish() % self.buckets.len() as u64) as usize;
-        self.buckets[idx].contains_key(key)
+        if self.buckets.is_empty() {
+            return Ok(keys.iter().map(|_| None).collect());
+        }
+        let mut located: Vec<(usize, usize, usize)> = keys
+            .iter()
+            .zip(&hashes)
+            .enumerate()
+            .filter_map(|(slot, (key, &hash))| {
+                let bkt = self.bucket_index(hash);
+                let idx = self.buckets[bkt].position(*key)?;
+                Some((bkt, idx, slot))
+            })
+            .collect();
+        located.sort_unstable();
+
+        // distinct keys have distinct locations, walked here in order
+        let mut values: Vec<Option<&'a mut V>> = keys.iter().map(|_| None).collect();
+        let mut located = located.into_iter().peekable();
+        let mut buckets = self.buckets.iter_mut();
+        let mut next_bucket = 0;
+        while let Some(&(bkt_idx, _, _)) = located.peek() {
+            let bkt = buckets.nth(bkt_idx - next_bucket).unwrap();
+            next_bucket = bkt_idx + 1;
+            let mut items = bkt.items.iter_mut();
+            let mut next_item = 0;
+            while let Some((_, idx, slot)) = located.next_if(|&(b, _, _)| b == bkt_idx) {
+                values[slot] = Some(&mut items.nth(idx - next_item).unwrap().1);
+                next_item = idx + 1;
+            }
+        }
+        Ok(values)
     }
 
-    fn get_bucket_mut(&mut self, key: &K) -> &mut Bucket<K, V> {
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
         if self.buckets.is_empty() {
-            self.resize();
+            return false;
         }
-        let mut hasher = DefaultHasher::new();
-        key.hash(&mut hasher);
-        let idx = (hasher.finish() % self.buckets.len() as u64) as usize;
-        &mut self.buckets[idx]
+        let idx = self.bucket_index(self.hash_key(key));
+        self.buckets[idx].contains_key(key)
     }
 
     pub fn insert(&mut self, key: K, value: V) -> Option<V> {
-        let l = self.buckets.len();
-        if l == 0 || self.num_items > 3 * l {
-            self.resize();
+        let hash = self.hash_key(&key);
+        self.insert_hashed(hash, key, value)
+    }
+
+    /// Same as `insert`, handing the pair back instead of aborting if the
+    /// map cannot allocate the memory it needs.
+    pub fn checked_insert(&mut self, key: K, value: V) -> Result<Option<V>, (K, V)> {
+        if self.try_grow_if_needed().is_err() {
+            return Err((key, value));
         }
-        self.num_items += 1;
-        self.get_bucket_mut(&key).insert(key, value)
+        let hash = self.hash_key(&key);
+        let idx = self.bucket_index(hash);
+        let bucket = &mut self.buckets[idx];
+        if bucket.items.try_reserve(1).is_err() || bucket.hashes.try_reserve(1).is_err() {
+            return Err((key, value));
+        }
+        Ok(self.insert_hashed(hash, key, value))
     }
 
-    pub fn insert_mut(&mut self, key: K, value: V) -> &mut V {
-        let l = self.buckets.len();
-        if l == 0 || self.num_items > 3 * l {
-            self.resize();
+    /// Inserts the pair, returning the previous key and value if an equal
+    /// key was present.
+    ///
+    /// Unlike `insert`, which keeps the stored key, the new key replaces the
+    /// old one.
+    pub fn replace(&mut self, key: K, value: V) -> Option<(K, V)> {
+        self.grow_if_needed();
+        let hash = self.hash_key(&key);
+        let idx = self.bucket_index(hash);
+        self.mark_occupied(idx);
+        let res = self.buckets[idx].replace(hash, key, value);
+        if res.is_none() {
+            self.num_items += 1;
         }
-        self.num_items += 1;
-        self.get_bucket_mut(&key).insert_mut(key, value)
+        res
     }
 
-    pub fn remove<Q: ?Sized>(&mut self, key: &Q) -> Option<V>
+    /// Hash of `key` as computed by this map, to be reused with the
+    /// `_prehashed` operations.
+    pub fn prehash<Q>(&self, key: &Q) -> u64
     where
         K: Borrow<Q>,
-        Q: Eq + Hash,
+        Q: Eq + Hash + ?Sized,
     {
-        let mut hasher = DefaultHasher::new();
-        key.hash(&mut hasher);
-        let idx = (hasher.finish() % self.buckets.len() as u64) as usize;
-        let bucket = &mut self.buckets[idx];
-        let res = bucket.remove(key);
-        if res.is_some() {
-            self.num_items -= 1;
+        self.hash_key(key)
+    }
+
+    /// Index of the bucket `key` maps to, whether or not it is present.
+    /// `None` while no bucket is allocated.
+    ///
+    /// The index changes whenever the map grows or shrinks.
+    pub fn bucket_index_of<Q>(&self, key: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        if self.buckets.is_empty() {
+            return None;
         }
-        res
+        Some(self.bucket_index(self.hash_key(key)))
     }
 
-    pub fn len(&self) -> usize {
-        self.num_items
+    /// Same as `get`, with `hash` obtained from `prehash(key)`.
+    pub fn get_prehashed<Q>(&self, hash: u64, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        if self.buckets.is_empty() {
+            return None;
+        }
+        self.buckets[self.bucket_index(hash)].get(key)
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.num_items == 0
+    /// Value of the first key satisfying `pred` in the bucket `hash` falls
+    /// in.
+    ///
+    /// Lower-level than `get_prehashed`, for lookups `Borrow` cannot
+    /// express. Keys outside this bucket are never tested.
+    pub fn get_by<F>(&self, hash: u64, pred: F) -> Option<&V>
+    where
+        F: Fn(&K) -> bool,
+    {
+        if self.buckets.is_empty() {
+            return None;
+        }
+        self.buckets[self.bucket_index(hash)]
+            .items
+            .iter()
+            .find(|(k, _)| pred(k))
+            .map(|(_, v)| v)
     }
 
-    fn resize(&mut self) {
-        let target_size = match self.buckets.len() {
-            0 => 1024,
-            n => 2 * n,
-        };
-        let mut hasher = DefaultHasher::new();
-        let mut new_buckets: Vec<Bucket<K, V>> = Vec::with_capacity(target_size);
-        new_buckets.extend((0..target_size).map(|_| Bucket::new()));
-        for (key, value) in self.buckets.iter_mut().flat_map(|bkt| bkt.items.drain(..)) {
-            key.hash(&mut hasher);
-            let idx = (hasher.finish() % new_buckets.len() as u64) as usize;
-            new_buckets[idx].insert(key, value);
+    /// Same as `insert`, with `hash` obtained from `prehash(&key)`.
+    ///
+    /// Passing any other hash misplaces the entry.
+    pub fn insert_prehashed(&mut self, hash: u64, key: K, value: V) -> Option<V> {
+        debug_assert_eq!(hash, self.hash_key(&key), "hash does not match key");
+        self.insert_hashed(hash, key, value)
+    }
+
+    fn insert_hashed(&mut self, hash: u64, key: K, value: V) -> Option<V> {
+        self.grow_if_needed();
+        let idx = self.bucket_index(hash);
+        self.mark_occupied(idx);
+        let res = self.buckets[idx].insert(hash, key, value);
+        if res.is_none() {
+            self.num_items += 1;
         }
-        mem::replace(&mut self.buckets, new_buckets);
+        res
     }
 
-    pub fn keys(&self) -> Keys<K, V> {
-        Keys::new(self)
+    pub fn insert_mut(&mut self, key: K, value: V) -> &mut V {
+        self.grow_if_needed();
+        let hash = self.hash_key(&key);
+        let idx = self.bucket_index(hash);
+        self.mark_occupied(idx);
+        let bucket = &mut self.buckets[idx];
+        if !bucket.contains_key(&key) {
+            self.num_items += 1;
+        }
+        bucket.insert_mut(hash, key, value)
     }
 
-    pub fn values(&self) -> Values<K, V> {
-        Values::new(self)
+    /// Returns the value at `key`, inserting `V::default()` first if absent.
+    ///
+    /// Same as `entry(key).or_insert_with(V::default)`, but hashes the key
+    /// only once and does not build an `Entry`.
+    pub fn get_mut_or_insert_default(&mut self, key: K) -> &mut V
+    where
+        V: Default,
+    {
+        self.get_or_insert_with_status(key, V::default).0
     }
 
-    pub fn entry(&'_ mut self, key: K) -> Entry<'_, K, V> {
-        Entry::new(self, key)
+    /// Returns the value at `key`, inserting `f()` first if absent.
+    ///
+    /// The flag is `true` when the entry was created by this call.
+    pub fn get_or_insert_with_status<F>(&mut self, key: K, f: F) -> (&mut V, bool)
+    where
+        F: FnOnce() -> V,
+    {
+        self.grow_if_needed();
+        let hash = self.hash_key(&key);
+        let idx = self.bucket_index(hash);
+        self.mark_occupied(idx);
+        let (value, inserted) = self.buckets[idx].get_or_insert_with(hash, key, f);
+        if inserted {
+            self.num_items += 1;
+        }
+        (value, inserted)
     }
-}
 
-pub struct HashMapIterator<'a, K: Eq + Hash, V> {
-    hmap: &'a HashMap<K, V>,
-    bucket_idx: usize,
-    bucket_at: usize,
-}
+    /// Returns a copy of the value at `key`, inserting `value` first if
+    /// absent. As the copy is owned, the map is not borrowed afterwards.
+    pub fn get_or_insert_cloned(&mut self, key: K, value: V) -> V
+    where
+        V: Clone,
+    {
+        self.get_or_insert_with_status(key, || value).0.clone()
+    }
 
-impl<'a, K: Eq + Hash, V> HashMapIterator<'a, K, V> {
-    pub fn new(hm: &'a HashMap<K, V>) -> Self {
-        HashMapIterator {
-            hmap: hm,
-            bucket_idx: 0,
-            bucket_at: 0,
+    /// Inserts every pair of `iter`, returning the keys and values they
+    /// displaced, in insertion order.
+    ///
+    /// As with `replace`, a new key replaces the equal one it displaces.
+    pub fn insert_all<I>(&mut self, iter: I) -> Vec<(K, V)>
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        iter.into_iter()
+            .filter_map(|(key, value)| self.replace(key, value))
+            .collect()
+    }
+
+    /// Inserts the pairs of `iter` whose key is absent, leaving present
+    /// values untouched. Returns how many pairs were inserted.
+    pub fn extend_absent<I>(&mut self, iter: I) -> usize
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let mut inserted = 0;
+        for (key, value) in iter {
+            if self.get_or_insert_with_status(key, || value).1 {
+                inserted += 1;
+            }
         }
+        inserted
     }
-}
 
-impl<'a, K: Eq + Hash, V> Iterator for HashMapIterator<'a, K, V> {
-    type Item = (&'a K, &'a V);
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.hmap.num_items == 0 {
+    /// Inserts the pairs of `iter` whose key is absent, so that the values
+    /// of `self` win when merging another map into it.
+    ///
+    /// Pairs with a present key are merged with one of three policies:
+    /// - `extend` overwrites the value and keeps the key already stored,
+    /// - `insert_all` replaces both and returns the displaced pairs,
+    /// - `extend_absent` and `extend_preferring_existing` drop the pair,
+    ///   the former also counting the pairs inserted.
+    pub fn extend_preferring_existing<I>(&mut self, iter: I)
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        self.extend_absent(iter);
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        if self.buckets.is_empty() {
             return None;
         }
-        loop {
-            match self.hmap.buckets.get(self.bucket_idx) {
-                None => break None, // no more bucket
-                Some(bkt) => {
-                    let new_pair = bkt.at(self.bucket_at);
-                    match new_pair {
-                        None => {
-                            // end of bucket, switch to next
-                            self.bucket_at = 0;
-                            self.bucket_idx += 1;
-                        }
-                        Some(p) => {
-                            // still some in current bucket
-                            self.bucket_at += 1;
-                            break Some(p).map(|(k, v)| (k, v));
-                        }
-                    }
-                }
-            }
+        let idx = self.bucket_index(self.hash_key(key));
+        let res = self.buckets[idx].remove(key);
+        if res.is_some() {
+            self.unmark_if_empty(idx);
+            debug_assert!(self.num_items > 0, "item count out of sync, see `recount`");
+            self.num_items -= 1;
+            self.shrink_if_sparse();
         }
+        res
     }
-}
 
-impl<'a, K: Eq + Hash, V> IntoIterator for &'a HashMap<K, V> {
-    type Item = (&'a K, &'a V);
-    type IntoIter = HashMapIterator<'a, K, V>;
-    fn into_iter(self) -> Self::IntoIter {
-        HashMapIterator::new(self)
+    /// Marks the bucket at `idx` as non-empty in `occupied`.
+    fn mark_occupied(&mut self, idx: usize) {
+        self.occupied[idx / 64] |= 1 << (idx % 64);
     }
-}
 
-impl<K: Eq + Hash, V> FromIterator<(K, V)> for HashMap<K, V> {
-    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
-        let mut hm = HashMap::new();
-        for (k, v) in iter {
-            hm.insert(k, v);
+    /// Clears the mark of the bucket at `idx` in `occupied` if it is empty.
+    fn unmark_if_empty(&mut self, idx: usize) {
+        if self.buckets[idx].items.is_empty() {
+            self.occupied[idx / 64] &= !(1 << (idx % 64));
         }
-        hm
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use crate::Bucket;
-    use crate::HashMap;
 
-    #[test]
-    fn create_insert() {
-        let mut m: HashMap<u64, String> = HashMap::new();
-        assert_eq!(m.num_items, 0);
-        assert!(m.insert(3, "hi".to_string()).is_none());
-        assert_eq!(m.num_items, 1);
+    /// Recomputes `occupied` from the buckets, after they were rebuilt or
+    /// many of them changed.
+    fn reindex_occupied(&mut self) {
+        self.occupied.clear();
+        self.occupied.resize(self.buckets.len().div_ceil(64), 0);
+        for (idx, bkt) in self.buckets.iter().enumerate() {
+            if !bkt.items.is_empty() {
+                self.occupied[idx / 64] |= 1 << (idx % 64);
+            }
+        }
     }
 
-    #[test]
-    fn insert_get_remove() {
-        let mut m: HashMap<u64, String> = HashMap::new();
-        assert!(m.insert(3, "hi".to_string()).is_none());
-        assert_eq!(m.get(&3), Some(&"hi".to_string()));
-        assert_eq!(m.remove(&3), Some("hi".to_string()));
-        assert_eq!(m.len(), 0)
+    /// Removes the item at `index` in the bucket at `bucket`.
+    fn remove_at(&mut self, bucket: usize, index: usize) -> (K, V) {
+        let res = self.buckets[bucket].remove_at(index);
+        self.unmark_if_empty(bucket);
+        debug_assert!(self.num_items > 0, "item count out of sync, see `recount`");
+        self.num_items -= 1;
+        self.shrink_if_sparse();
+        res
     }
 
-    #[test]
-    fn iter_on_bucket() {
-        let mut bkt: Bucket<u64, String> = Bucket::new();
-        assert!(bkt.insert(3, "hi".to_string()).is_none());
-        assert!(bkt.insert(2, "hi".to_string()).is_none());
-        assert!(bkt.insert(1, "hi".to_string()).is_none());
-        let mut nitems = 0;
-        for (k, v) in bkt.into_iter() {
-            nitems += 1;
-            assert!(k <= &3);
-            assert_eq!(v, &"hi".to_string());
+    /// Swaps the values of `a` and `b`, returning `false` and leaving the
+    /// map untouched unless both are present.
+    pub fn swap<Q>(&mut self, a: &Q, b: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let (first, second) = match (self.locate(a), self.locate(b)) {
+            (Some(x), Some(y)) if x < y => (x, y),
+            (Some(x), Some(y)) => (y, x),
+            _ => return false,
+        };
+        if first.0 == second.0 {
+            let items = &mut self.buckets[first.0].items;
+            if first.1 != second.1 {
+                let (left, right) = items.split_at_mut(second.1);
+                mem::swap(&mut left[first.1].1, &mut right[0].1);
+            }
+        } else {
+            let (left, right) = self.buckets.split_at_mut(second.0);
+            mem::swap(
+                &mut left[first.0].items[first.1].1,
+                &mut right[0].items[second.1].1,
+            );
         }
-        assert_eq!(nitems, 3);
+        true
     }
 
-    #[test]
-    fn iter_on_hashmap() {
-        let mut m: HashMap<u64, String> = HashMap::new();
-        assert_eq!(m.insert(3, "hi".to_string()), None);
-        assert_eq!(m.insert(4, "hi".to_string()), None);
-        assert_eq!(m.insert(5, "hi".to_string()), None);
-        assert_eq!(m.insert(0, "hi".to_string()), None);
+    /// Replaces the value of `key` with `replacement`, returning the old
+    /// value. Unlike `insert`, an absent key is left absent and `None` is
+    /// returned.
+    pub fn take_replace<Q>(&mut self, key: &Q, replacement: V) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.get_mut(key)
+            .map(|value| mem::replace(value, replacement))
+    }
+
+    /// Removes every key yielded by `keys`, returning how many were present.
+    pub fn remove_all<Q, I>(&mut self, keys: I) -> usize
+    where
+        I: IntoIterator<Item = Q>,
+        K: Borrow<Q>,
+        Q: Eq + Hash,
+    {
+        let mut removed = 0;
+        for key in keys {
+            if self.remove(&key).is_some() {
+                removed += 1;
+            }
+        }
+        removed
+    }
+
+    /// Keeps only the entries for which `f` holds, returning how many were
+    /// removed.
+    pub fn retain<F>(&mut self, mut f: F) -> usize
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        let removed: usize = self.buckets.iter_mut().map(|bkt| bkt.retain(&mut f)).sum();
+        self.reindex_occupied();
+        debug_assert!(
+            self.num_items >= removed,
+            "item count out of sync, see `recount`"
+        );
+        self.num_items -= removed;
+        self.shrink_if_sparse();
+        removed
+    }
+
+    /// Keeps only the entries whose value satisfies `f`, returning how many
+    /// were removed.
+    pub fn retain_values<F>(&mut self, mut f: F) -> usize
+    where
+        F: FnMut(&V) -> bool,
+    {
+        self.retain(|_, v| f(v))
+    }
+
+    /// Removes the entries for which `f` holds and returns them, in bucket
+    /// order.
+    pub fn drain_where<F>(&mut self, mut f: F) -> Vec<(K, V)>
+    where
+        F: FnMut(&K, &V) -> bool,
+    {
+        let mut drained = Vec::new();
+        for bkt in self.buckets.iter_mut() {
+            let mut i = 0;
+            while i < bkt.items.len() {
+                let (k, v) = &bkt.items[i];
+                if f(k, v) {
+                    drained.push(bkt.remove_at(i));
+                } else {
+                    i += 1;
+                }
+            }
+        }
+        self.reindex_occupied();
+        debug_assert!(
+            self.num_items >= drained.len(),
+            "item count out of sync, see `recount`"
+        );
+        self.num_items -= drained.len();
+        self.shrink_if_sparse();
+        drained
+    }
+
+    /// Passes every value by value to `f`, which either returns the value to
+    /// keep for the entry or `None` to remove it. Returns how many entries
+    /// were removed.
+    pub fn retain_map<F>(&mut self, mut f: F) -> usize
+    where
+        F: FnMut(&K, V) -> Option<V>,
+    {
+        let removed: usize = self
+            .buckets
+            .iter_mut()
+            .map(|bkt| bkt.retain_map(&mut f))
+            .sum();
+        debug_assert!(
+            self.num_items >= removed,
+            "item count out of sync, see `recount`"
+        );
+        self.num_items -= removed;
+        self.shrink_if_sparse();
+        removed
+    }
+
+    /// Applies `update` to every value whose entry satisfies `pred`,
+    /// returning how many were updated.
+    pub fn update_where<P, U>(&mut self, pred: P, update: U) -> usize
+    where
+        P: Fn(&K, &V) -> bool,
+        U: Fn(&mut V),
+    {
+        let mut updated = 0;
+        for (k, v) in self.buckets.iter_mut().flat_map(|bkt| bkt.items.iter_mut()) {
+            if pred(k, v) {
+                update(v);
+                updated += 1;
+            }
+        }
+        updated
+    }
+
+    /// Removes an entry from the first non-empty bucket at or after
+    /// `start_bucket`, wrapping around the bucket array.
+    ///
+    /// Consumers draining a shared map can start at different offsets to
+    /// work on different buckets. The map is not shrunk, so bucket indices
+    /// stay valid while draining.
+    pub fn pop_from(&mut self, start_bucket: usize) -> Option<(K, V)> {
+        let bucket_count = self.buckets.len();
+        if bucket_count == 0 {
+            return None;
+        }
+        let start = start_bucket % bucket_count;
+        let idx = (start..bucket_count)
+            .chain(0..start)
+            .find(|&idx| !self.buckets[idx].items.is_empty())?;
+        let res = self.buckets[idx].pop();
+        self.unmark_if_empty(idx);
+        debug_assert!(self.num_items > 0, "item count out of sync, see `recount`");
+        self.num_items -= 1;
+        res
+    }
+
+    /// Removes all entries, keeping the allocated buckets for reuse.
+    ///
+    /// `capacity()` is unchanged, so refilling the map up to its previous
+    /// size does not resize. Use `clear_and_shrink` to release the buckets.
+    pub fn clear(&mut self) {
+        for bkt in self.buckets.iter_mut() {
+            bkt.clear();
+        }
+        self.occupied.iter_mut().for_each(|word| *word = 0);
+        self.num_items = 0;
+    }
+
+    /// Removes all entries and releases the buckets, leaving the map as
+    /// unallocated as a new one.
+    pub fn clear_and_shrink(&mut self) {
+        self.buckets = Vec::new();
+        self.occupied = Vec::new();
+        self.num_items = 0;
+        self.min_buckets = 0;
+    }
+
+    /// Removes the entries of the buckets whose index is in `range`,
+    /// releasing their memory, and returns how many were removed.
+    ///
+    /// The bucket array is left as is so that indices stay valid across
+    /// calls, which lets a large map be emptied a range at a time. Indices
+    /// past the last bucket are ignored.
+    pub fn clear_bucket_range(&mut self, range: Range<usize>) -> usize {
+        let end = range.end.min(self.buckets.len());
+        let start = range.start.min(end);
+        let removed: usize = self.buckets[start..end]
+            .iter_mut()
+            .map(|bkt| mem::take(bkt).items.len())
+            .sum();
+        self.reindex_occupied();
+        debug_assert!(
+            self.num_items >= removed,
+            "item count out of sync, see `recount`"
+        );
+        self.num_items -= removed;
+        removed
+    }
+
+    /// Recomputes the number of items from the buckets, fixing the stored
+    /// count if it drifted, and returns it.
+    pub fn recount(&mut self) -> usize {
+        self.num_items = self.buckets.iter().map(|bkt| bkt.items.len()).sum();
+        self.num_items
+    }
+
+    pub fn len(&self) -> usize {
+        self.num_items
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.num_items == 0
+    }
+
+    /// Shrinks the bucket array as much as possible while still holding
+    /// the current items and at least `min_capacity` items overall.
+    pub fn shrink_to(&mut self, min_capacity: usize) {
+        let target_size = capacity_for(self.num_items.max(min_capacity));
+        self.min_buckets = self.min_buckets.min(target_size);
+        if target_size < self.buckets.len() {
+            self.rehash_into(target_size);
+        }
+    }
+
+    /// Shrinks the bucket array as much as possible for the current items.
+    pub fn shrink_to_fit(&mut self) {
+        self.shrink_to(0);
+    }
+
+    /// Average number of items per bucket, `0.0` when nothing is allocated.
+    pub fn load_factor(&self) -> f64 {
+        if self.buckets.is_empty() {
+            return 0.0;
+        }
+        self.num_items as f64 / self.buckets.len() as f64
+    }
+
+    /// Whether the load factor exceeds the threshold past which the next
+    /// insertion grows the map.
+    pub fn needs_resize(&self) -> bool {
+        self.num_items > MAX_LOAD_FACTOR * self.buckets.len()
+    }
+
+    fn grow_if_needed(&mut self) {
+        if self.buckets.is_empty() || self.needs_resize() {
+            self.resize();
+        }
+    }
+
+    /// Grows the bucket array and rehashes every item.
+    ///
+    /// Insertions do this on their own once `needs_resize` holds; calling it
+    /// earlier lets users pick when the cost is paid.
+    pub fn rehash(&mut self) {
+        self.resize();
+    }
+
+    /// Moves every item into `scratch`, which becomes the bucket array, and
+    /// returns the previous one.
+    ///
+    /// The map ends up with `bucket_count` buckets, rounded up to a power of
+    /// two as with `resize_to`. Buckets left in `scratch` are emptied and
+    /// reused, and the returned buckets are empty but keep their
+    /// allocations, so two bucket arrays can be swapped back and forth
+    /// without allocating once they are large enough.
+    pub fn resize_into(
+        &mut self,
+        bucket_count: usize,
+        mut scratch: Vec<Bucket<K, V>>,
+    ) -> Vec<Bucket<K, V>> {
+        let target_size = bucket_count
+            .max(1)
+            .checked_next_power_of_two()
+            .expect("bucket count overflows usize");
+        scratch.iter_mut().for_each(Bucket::clear);
+        scratch.resize_with(target_size, Bucket::new);
+        for bkt in self.buckets.iter_mut() {
+            for (hash, (key, value)) in bkt.hashes.drain(..).zip(bkt.items.drain(..)) {
+                scratch[index_for(hash, target_size)].push(hash, key, value);
+            }
+        }
+        self.min_buckets = target_size;
+        let old = mem::replace(&mut self.buckets, scratch);
+        self.reindex_occupied();
+        old
+    }
+
+    /// Grows the bucket array so that holding `expected_items` leaves a load
+    /// factor close to, and at most, `target_load`.
+    ///
+    /// Loads above `MAX_LOAD_FACTOR` are not reached, as insertions grow the
+    /// map past it.
+    pub fn reserve_for_load(&mut self, expected_items: usize, target_load: f64) {
+        assert!(target_load > 0.0, "target load factor must be positive");
+        let needed = (expected_items as f64 / target_load).ceil() as usize;
+        let target_size = needed
+            .max(1)
+            .checked_next_power_of_two()
+            .expect("bucket count overflows usize");
+        self.min_buckets = self.min_buckets.max(target_size);
+        if target_size > self.buckets.len() {
+            self.rehash_into(target_size);
+        }
+    }
+
+    /// Reserves room for `per_bucket_capacity` items in every bucket,
+    /// allocating the bucket array first if needed, so that insertions do
+    /// not allocate until a bucket outgrows it.
+    ///
+    /// Growing or shrinking the map rebuilds the buckets, which loses the
+    /// reserved room.
+    pub fn prefault(&mut self, per_bucket_capacity: usize) {
+        if self.buckets.is_empty() {
+            self.resize();
+        }
+        self.min_buckets = self.min_buckets.max(self.buckets.len());
+        for bkt in self.buckets.iter_mut() {
+            let len = bkt.items.len();
+            bkt.items.reserve(per_bucket_capacity.saturating_sub(len));
+            bkt.hashes.reserve(per_bucket_capacity.saturating_sub(len));
+        }
+    }
+
+    /// Rehashes every item into `bucket_count` buckets, rounded up to a
+    /// power of two.
+    ///
+    /// Later insertions and removals still grow or shrink the map once it
+    /// crosses the load watermarks.
+    pub fn resize_to(&mut self, bucket_count: usize) {
+        let target_size = bucket_count
+            .max(1)
+            .checked_next_power_of_two()
+            .expect("bucket count overflows usize");
+        self.min_buckets = target_size;
+        if target_size != self.buckets.len() {
+            self.rehash_into(target_size);
+        }
+    }
+
+    /// Halves the bucket array while the load factor is under the shrink
+    /// watermark, without going below `DEFAULT_BUCKETS` or the bucket count
+    /// set by explicit sizing.
+    fn shrink_if_sparse(&mut self) {
+        let floor = DEFAULT_BUCKETS.max(self.min_buckets);
+        let mut target_size = self.buckets.len();
+        while target_size > floor && self.num_items * MIN_LOAD_INVERSE < target_size {
+            target_size /= 2;
+        }
+        if target_size < self.buckets.len() {
+            self.rehash_into(target_size);
+        }
+    }
+
+    fn resize(&mut self) {
+        if let Some(target_size) = grown_size(self.buckets.len()) {
+            let new_buckets = Vec::with_capacity(target_size);
+            let alloc = |n| Ok::<_, Infallible>(Bucket::with_capacity(n));
+            if let Err(never) = self.grow_into(target_size, new_buckets, alloc) {
+                match never {}
+            }
+        }
+    }
+
+    /// Same as `grow_if_needed`, failing instead of aborting if the new
+    /// buckets cannot be allocated. The map is then left untouched.
+    fn try_grow_if_needed(&mut self) -> Result<(), TryReserveError> {
+        if !self.buckets.is_empty() && !self.needs_resize() {
+            return Ok(());
+        }
+        if let Some(target_size) = grown_size(self.buckets.len()) {
+            let mut new_buckets = Vec::new();
+            new_buckets.try_reserve_exact(target_size)?;
+            self.grow_into(target_size, new_buckets, Bucket::try_with_capacity)?;
+        }
+        Ok(())
+    }
+
+    /// Moves the items into `new_buckets`, an empty array with room for
+    /// `target_size` buckets, `target_size` being given by `grown_size`.
+    ///
+    /// Growing doubles the bucket count `n`, which splits every bucket `i`
+    /// into buckets `i` and `i + n`. The bit of the cached hash selecting
+    /// the new bucket is `n` itself, so no key is hashed again. A bucket
+    /// whose items all go the same way is moved as is instead of being
+    /// reallocated. A split bucket keeps its storage for the items staying
+    /// at `i`, and only the items moving to `i + n` get a new bucket.
+    ///
+    /// These new buckets are allocated with `alloc` before any item is
+    /// moved, so that the map is left untouched if it fails.
+    fn grow_into<E, F>(
+        &mut self,
+        target_size: usize,
+        mut new_buckets: Vec<Bucket<K, V>>,
+        mut alloc: F,
+    ) -> Result<(), E>
+    where
+        F: FnMut(usize) -> Result<Bucket<K, V>, E>,
+    {
+        let old_size = self.buckets.len();
+        debug_assert!(old_size == 0 || target_size == 2 * old_size);
+        let moving_count = |idx: usize, bkt: &Bucket<K, V>| {
+            bkt.hashes
+                .iter()
+                .filter(|&&hash| index_for(hash, target_size) != idx)
+                .count()
+        };
+        new_buckets.resize_with(target_size, Bucket::new);
+        for (idx, bkt) in self.buckets.iter().enumerate() {
+            let moving = moving_count(idx, bkt);
+            if moving != 0 && moving != bkt.items.len() {
+                new_buckets[idx + old_size] = alloc(moving)?;
+            }
+        }
+        for (idx, mut bkt) in mem::take(&mut self.buckets).into_iter().enumerate() {
+            let moving = moving_count(idx, &bkt);
+            if moving == 0 {
+                new_buckets[idx] = bkt;
+            } else if moving == bkt.items.len() {
+                new_buckets[idx + old_size] = bkt;
+            } else {
+                let moved = &mut new_buckets[idx + old_size];
+                bkt.move_where(moved, |hash| index_for(hash, target_size) != idx);
+                new_buckets[idx] = bkt;
+            }
+        }
+        self.buckets = new_buckets;
+        self.reindex_occupied();
+        Ok(())
+    }
+
+    /// Moves all items into `target_size` fresh buckets.
+    ///
+    /// Items are placed from their cached hashes, so no key is hashed again.
+    /// Targets are counted in a first pass so that every new bucket is
+    /// allocated once at its exact final size.
+    fn rehash_into(&mut self, target_size: usize) {
+        let mut counts = vec![0; target_size];
+        for &hash in self.buckets.iter().flat_map(|bkt| bkt.hashes.iter()) {
+            counts[index_for(hash, target_size)] += 1;
+        }
+        let mut new_buckets: Vec<Bucket<K, V>> = Vec::with_capacity(target_size);
+        new_buckets.extend(counts.into_iter().map(Bucket::with_capacity));
+        for bkt in mem::take(&mut self.buckets) {
+            for (hash, (key, value)) in bkt.hashes.into_iter().zip(bkt.items) {
+                new_buckets[index_for(hash, target_size)].push(hash, key, value);
+            }
+        }
+        self.buckets = new_buckets;
+        self.reindex_occupied();
+    }
+
+    /// Hashes every key again, replacing the cached hashes, and moves the
+    /// items to the buckets of their new hashes.
+    fn rehash_keys(&mut self) {
+        let mut buckets = mem::take(&mut self.buckets);
+        for bkt in buckets.iter_mut() {
+            for (hash, (key, _)) in bkt.hashes.iter_mut().zip(&bkt.items) {
+                *hash = self.hash_key(key);
+            }
+        }
+        self.buckets = buckets;
+        self.rehash_into(self.buckets.len());
+    }
+
+    /// Adds every value of `other` into the value at the same key,
+    /// inserting a copy of it for keys missing from `self`.
+    pub fn add_assign_all(&mut self, other: &HashMap<K, V, S>)
+    where
+        K: Clone,
+        V: AddAssign + Clone,
+    {
+        for (key, value) in other {
+            let (slot, inserted) = self.get_or_insert_with_status(key.clone(), || value.clone());
+            if !inserted {
+                *slot += value.clone();
+            }
+        }
+    }
+
+    /// Consumes the map, splitting it into the entries matching `f` and
+    /// the ones that do not.
+    pub fn partition<F>(self, mut f: F) -> (HashMap<K, V, S>, HashMap<K, V, S>)
+    where
+        F: FnMut(&K, &V) -> bool,
+        S: Clone,
+    {
+        let mut matching = HashMap::with_hasher(self.hash_builder.clone());
+        let mut non_matching = HashMap::with_hasher(self.hash_builder.clone());
+        matching.seed = self.seed;
+        non_matching.seed = self.seed;
+        for (key, value) in self.into_items() {
+            if f(&key, &value) {
+                matching.insert(key, value);
+            } else {
+                non_matching.insert(key, value);
+            }
+        }
+        (matching, non_matching)
+    }
+
+    /// Consumes the map, splitting it into `n` maps each owning a contiguous
+    /// range of its buckets.
+    ///
+    /// Items are placed in their new map from their cached hash, so keys are
+    /// not hashed again. Some maps are empty if there are fewer buckets
+    /// than `n`.
+    pub fn split(self, n: usize) -> Vec<HashMap<K, V, S>>
+    where
+        S: Clone,
+    {
+        assert!(n > 0, "cannot split a map into zero parts");
+        let chunk = self.buckets.len().div_ceil(n).max(1);
+        let mut buckets = self.buckets.into_iter();
+        let mut parts = Vec::with_capacity(n);
+        for _ in 0..n {
+            let range: Vec<Bucket<K, V>> = buckets.by_ref().take(chunk).collect();
+            let num_items = range.iter().map(|bkt| bkt.items.len()).sum();
+            let mut part = HashMap::with_capacity_and_hasher(num_items, self.hash_builder.clone());
+            part.seed = self.seed;
+            part.min_buckets = 0;
+            for bkt in range {
+                for (hash, (key, value)) in bkt.hashes.into_iter().zip(bkt.items) {
+                    let idx = part.bucket_index(hash);
+                    part.mark_occupied(idx);
+                    part.buckets[idx].push(hash, key, value);
+                }
+            }
+            part.num_items = num_items;
+            parts.push(part);
+        }
+        parts
+    }
+
+    /// Consumes the map, returning its keys in ascending order.
+    pub fn into_sorted_keys(self) -> Vec<K>
+    where
+        K: Ord,
+    {
+        let mut keys = Vec::with_capacity(self.num_items);
+        keys.extend(self.into_items().map(|(key, _)| key));
+        keys.sort_unstable();
+        keys
+    }
+
+    /// Consumes the map, moving its entries into a `BTreeMap` ordered by
+    /// key.
+    pub fn into_btree_map(self) -> BTreeMap<K, V>
+    where
+        K: Ord,
+    {
+        self.into_items().collect()
+    }
+
+    /// Consumes the map, returning the `k` entries with the largest values,
+    /// by descending value.
+    pub fn into_top_k(self, k: usize) -> Vec<(K, V)>
+    where
+        V: Ord,
+    {
+        let mut entries: Vec<(K, V)> = Vec::with_capacity(self.num_items);
+        entries.extend(self.into_items());
+        if k < entries.len() {
+            // partition around the k-th largest, then only sort the top
+            entries.select_nth_unstable_by(k, |a, b| b.1.cmp(&a.1));
+            entries.truncate(k);
+        }
+        entries.sort_unstable_by(|a, b| b.1.cmp(&a.1));
+        entries
+    }
+
+    fn into_items(self) -> IntoIter<K, V> {
+        IntoIter {
+            buckets: self.buckets.into_iter(),
+            items: Vec::new().into_iter(),
+            remaining: self.num_items,
+        }
+    }
+
+    /// Number of entries for which `pred` holds.
+    pub fn count_where<P>(&self, pred: P) -> usize
+    where
+        P: Fn(&K, &V) -> bool,
+    {
+        if self.num_items == 0 {
+            return 0;
+        }
+        self.iter().filter(|&(k, v)| pred(k, v)).count()
+    }
+
+    /// Folds every value into an accumulator starting at `init`.
+    pub fn fold_values<B, F>(&self, init: B, f: F) -> B
+    where
+        F: FnMut(B, &V) -> B,
+    {
+        self.values().fold(init, f)
+    }
+
+    /// Sum of all values, `V::default()` for an empty map.
+    pub fn sum_values(&self) -> V
+    where
+        V: Default + AddAssign + Copy,
+    {
+        self.fold_values(V::default(), |mut sum, &value| {
+            sum += value;
+            sum
+        })
+    }
+
+    /// Entry with the largest value, `None` if the map is empty. Ties are
+    /// broken arbitrarily.
+    pub fn max_by_value(&self) -> Option<(&K, &V)>
+    where
+        V: Ord,
+    {
+        self.iter().max_by(|a, b| a.1.cmp(b.1))
+    }
+
+    /// Entry with the smallest value, `None` if the map is empty. Ties are
+    /// broken arbitrarily.
+    pub fn min_by_value(&self) -> Option<(&K, &V)>
+    where
+        V: Ord,
+    {
+        self.iter().min_by(|a, b| a.1.cmp(b.1))
+    }
+
+    /// Collects references to all entries, sorted with `cmp`.
+    pub fn entries_sorted_by<F>(&self, mut cmp: F) -> Vec<(&K, &V)>
+    where
+        F: FnMut(&(&K, &V), &(&K, &V)) -> Ordering,
+    {
+        let mut entries: Vec<(&K, &V)> = self.into_iter().collect();
+        entries.sort_by(|a, b| cmp(a, b));
+        entries
+    }
+
+    pub fn values_mut(&mut self) -> ValuesMut<'_, K, V> {
+        ValuesMut::new(self)
+    }
+
+    /// Multiplies every value by `factor` in place.
+    pub fn scale_values(&mut self, factor: V)
+    where
+        V: MulAssign + Copy,
+    {
+        for value in self.values_mut() {
+            *value *= factor;
+        }
+    }
+
+    /// Whether every cached hash matches its key and bucket.
+    #[cfg(test)]
+    fn verify_cached_hashes(&self) -> bool {
+        self.buckets.iter().enumerate().all(|(idx, bkt)| {
+            bkt.hashes.len() == bkt.items.len()
+                && bkt.hashes.iter().zip(&bkt.items).all(|(&hash, (key, _))| {
+                    hash == self.hash_key(key) && self.bucket_index(hash) == idx
+                })
+        })
+    }
+
+    /// Iterator on the entries of the map.
+    pub fn entries(&self) -> Entries<'_, K, V> {
+        Entries::new(self)
+    }
+
+    /// Same as `entries`.
+    pub fn iter(&self) -> Entries<'_, K, V> {
+        self.entries()
+    }
+
+    /// Iterator on the entries satisfying `pred`.
+    pub fn filter_entries<'a, P>(&'a self, pred: P) -> impl Iterator<Item = (&'a K, &'a V)> + 'a
+    where
+        P: Fn(&K, &V) -> bool + 'a,
+    {
+        self.iter().filter(move |&(k, v)| pred(k, v))
+    }
+
+    pub fn keys(&self) -> Keys<'_, K, V> {
+        Keys::new(self)
+    }
+
+    pub fn values(&self) -> Values<'_, K, V> {
+        Values::new(self)
+    }
+
+    pub fn entry(&'_ mut self, key: K) -> Entry<'_, K, V, S> {
+        Entry::new(self, key)
+    }
+
+    /// Occupied entry of `key`, `None` if it is absent.
+    ///
+    /// Unlike `entry`, no owned key is needed.
+    pub fn get_entry_mut<Q>(&mut self, key: &Q) -> Option<OccupiedEntry<'_, K, V, S>>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let (bucket, index) = self.locate(key)?;
+        Some(OccupiedEntry::new(self, bucket, index))
+    }
+}
+
+/// Error of `HashMap::get_mut_many` when the key at `first` is requested
+/// again at `second`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DuplicateKeyError {
+    pub first: usize,
+    pub second: usize,
+}
+
+impl fmt::Display for DuplicateKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "keys at positions {} and {} are equal",
+            self.first, self.second
+        )
+    }
+}
+
+impl std::error::Error for DuplicateKeyError {}
+
+/// Iterator on the entries of a hash map.
+///
+/// Non-empty buckets are found through the occupancy bitmap of the map,
+/// which tells 64 buckets apart at a time, so empty buckets are never
+/// examined. Iterating costs one step per item plus one word per 64
+/// buckets up to the last non-empty one.
+pub struct HashMapIterator<'a, K: Eq + Hash, V> {
+    buckets: &'a [Bucket<K, V>],
+    occupied: &'a [u64],
+    /// Index in `occupied` of the word in `bits`.
+    word: usize,
+    /// Bits of the next non-empty buckets in the current word.
+    bits: u64,
+    items: std::slice::Iter<'a, (K, V)>,
+    remaining: usize,
+}
+
+impl<'a, K: Eq + Hash, V> HashMapIterator<'a, K, V> {
+    pub fn new<S>(hm: &'a HashMap<K, V, S>) -> Self {
+        HashMapIterator {
+            buckets: &hm.buckets,
+            occupied: &hm.occupied,
+            word: 0,
+            bits: hm.occupied.first().copied().unwrap_or(0),
+            items: [].iter(),
+            remaining: hm.num_items,
+        }
+    }
+}
+
+impl<'a, K: Eq + Hash, V> Clone for HashMapIterator<'a, K, V> {
+    fn clone(&self) -> Self {
+        HashMapIterator {
+            buckets: self.buckets,
+            occupied: self.occupied,
+            word: self.word,
+            bits: self.bits,
+            items: self.items.clone(),
+            remaining: self.remaining,
+        }
+    }
+}
+
+impl<'a, K: Eq + Hash, V> Iterator for HashMapIterator<'a, K, V> {
+    type Item = (&'a K, &'a V);
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((k, v)) = self.items.next() {
+                self.remaining = self.remaining.saturating_sub(1);
+                return Some((k, v));
+            }
+            if self.remaining == 0 {
+                return None;
+            }
+            // end of bucket, jump to the next non-empty one
+            while self.bits == 0 {
+                self.word += 1;
+                self.bits = *self.occupied.get(self.word)?;
+            }
+            let bit = self.bits.trailing_zeros() as usize;
+            self.bits &= self.bits - 1;
+            #[cfg(test)]
+            crate::testing::count_bucket_visit();
+            self.items = self.buckets[self.word * 64 + bit].items.iter();
+        }
+    }
+}
+
+impl<'a, K: Eq + Hash, V, S> IntoIterator for &'a HashMap<K, V, S> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = HashMapIterator<'a, K, V>;
+    fn into_iter(self) -> Self::IntoIter {
+        HashMapIterator::new(self)
+    }
+}
+
+/// Owning iterator on the entries of a hash map, in bucket order.
+pub struct IntoIter<K: Eq + Hash, V> {
+    buckets: std::vec::IntoIter<Bucket<K, V>>,
+    items: std::vec::IntoIter<(K, V)>,
+    remaining: usize,
+}
+
+impl<K: Eq + Hash, V> Iterator for IntoIter<K, V> {
+    type Item = (K, V);
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(item) = self.items.next() {
+                self.remaining = self.remaining.saturating_sub(1);
+                return Some(item);
+            }
+            if self.remaining == 0 {
+                return None;
+            }
+            self.items = self.buckets.next()?.items.into_iter();
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher> IntoIterator for HashMap<K, V, S> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.into_items()
+    }
+}
+
+impl<K: Eq + Hash, V> HashMap<K, Vec<V>> {
+    /// Groups the values of `iter` by key, keeping their order within each
+    /// group.
+    pub fn group_by<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut groups = HashMap::new();
+        for (key, value) in iter {
+            groups.entry(key).or_insert_with(Vec::new).push(value);
+        }
+        groups
+    }
+}
+
+impl<V, S: BuildHasher> HashMap<usize, V, S> {
+    /// Inserts `k -> f(k)` for every `k` in `range`, growing the map once
+    /// beforehand.
+    pub fn fill_range<F>(&mut self, range: Range<usize>, mut f: F)
+    where
+        F: FnMut(usize) -> V,
+    {
+        let target_size = capacity_for(self.num_items + range.len());
+        self.min_buckets = self.min_buckets.max(target_size);
+        if target_size > self.buckets.len() {
+            self.rehash_into(target_size);
+        }
+        for k in range {
+            self.insert(k, f(k));
+        }
+    }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher + Default> FromIterator<(K, V)> for HashMap<K, V, S> {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let mut hm = HashMap::default();
+        for (k, v) in iter {
+            hm.insert(k, v);
+        }
+        hm
+    }
+}
+
+/// Pairs with a key already present overwrite its value, see
+/// `extend_preferring_existing` for the other merge policies.
+impl<K: Eq + Hash, V, S: BuildHasher> Extend<(K, V)> for HashMap<K, V, S> {
+    fn extend<T: IntoIterator<Item = (K, V)>>(&mut self, iter: T) {
+        for (k, v) in iter {
+            self.insert(k, v);
+        }
+    }
+}
+
+/// Copies the pairs, overwriting the values of present keys as with
+/// `Extend<(K, V)>`.
+impl<'a, K, V, S> Extend<(&'a K, &'a V)> for HashMap<K, V, S>
+where
+    K: Eq + Hash + Copy,
+    V: Copy,
+    S: BuildHasher,
+{
+    fn extend<T: IntoIterator<Item = (&'a K, &'a V)>>(&mut self, iter: T) {
+        self.extend(iter.into_iter().map(|(&k, &v)| (k, v)));
+    }
+}
+
+impl<K: Eq + Hash + fmt::Debug, V: fmt::Debug, S> fmt::Debug for HashMap<K, V, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self).finish()
+    }
+}
+
+/// Maps are equal when they hold the same keys with equal values,
+/// whatever their bucket layout.
+impl<K: Eq + Hash, V: PartialEq, S: BuildHasher> PartialEq for HashMap<K, V, S> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().all(|(k, v)| other.get(k) == Some(v))
+    }
+}
+
+impl<K: Eq + Hash, V: Eq, S: BuildHasher> Eq for HashMap<K, V, S> {}
+
+/// Entries are hashed on their own and the results summed, so the hash
+/// does not depend on iteration order and agrees with `PartialEq`.
+impl<K: Eq + Hash, V: Hash, S> Hash for HashMap<K, V, S> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let sum = self.into_iter().fold(0u64, |sum, entry| {
+            sum.wrapping_add(DefaultHashBuilder::default().hash_one(entry))
+        });
+        state.write_usize(self.num_items);
+        state.write_u64(sum);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{take_bucket_visits, Colliding, Counted, Fnv, Identity};
+    use crate::Bucket;
+    use crate::HashMap;
+    use crate::{
+        capacity_for, grown_size, index_for, DefaultHashBuilder, DEFAULT_BUCKETS, MAX_LOAD_FACTOR,
+        MIN_BUCKETS, MIN_LOAD_INVERSE,
+    };
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, BuildHasherDefault};
+
+    #[test]
+    fn create_insert() {
+        let mut m: HashMap<u64, String> = HashMap::new();
+        assert_eq!(m.num_items, 0);
+        assert!(m.insert(3, "hi".to_string()).is_none());
+        assert_eq!(m.num_items, 1);
+    }
+
+    #[test]
+    fn insert_get_remove() {
+        let mut m: HashMap<u64, String> = HashMap::new();
+        assert!(m.insert(3, "hi".to_string()).is_none());
+        assert_eq!(m.get(&3), Some(&"hi".to_string()));
+        assert_eq!(m.remove(&3), Some("hi".to_string()));
+        assert_eq!(m.len(), 0)
+    }
+
+    #[test]
+    fn insert_existing_key_keeps_len() {
+        let mut m: HashMap<u64, u64> = HashMap::new();
+        assert_eq!(m.insert(1, 1), None);
+        assert_eq!(m.insert(1, 2), Some(1));
+        *m.insert_mut(1, 3) += 1;
+        assert_eq!(m.len(), 1);
+        assert_eq!(m[&1], 4);
+    }
+
+    #[test]
+    fn hash_key_is_shared() {
+        let m: HashMap<String, u32> = HashMap::new();
+        assert_eq!(m.hash_key("viking"), m.hash_key("viking"));
+        assert_eq!(m.hash_key(&"viking".to_string()), m.hash_key("viking"));
+
+        let mut m = HashMap::new();
+        Counted::take_hash_calls();
+        m.insert(Counted(1), 1);
+        assert_eq!(Counted::take_hash_calls(), 1);
+        m.get(&Counted(1));
+        m.get_mut(&Counted(1));
+        m.contains_key(&Counted(1));
+        m.remove(&Counted(1));
+        assert_eq!(Counted::take_hash_calls(), 4);
+        m.insert_mut(Counted(2), 2);
+        m.get_mut_or_insert_default(Counted(2));
+        assert_eq!(Counted::take_hash_calls(), 2);
+    }
+
+    #[test]
+    fn load_factor_and_resize_threshold() {
+        let mut m: HashMap<usize, usize> = HashMap::new();
+        assert_eq!(m.load_factor(), 0.0);
+        assert!(!m.needs_resize());
+        m.insert(0, 0);
+        let buckets = m.buckets.len();
+        let mut last = m.load_factor();
+        for i in 1..MAX_LOAD_FACTOR * buckets {
+            m.insert(i, i);
+            assert!(m.load_factor() > last);
+            last = m.load_factor();
+            assert!(!m.needs_resize());
+        }
+        assert_eq!(m.load_factor(), MAX_LOAD_FACTOR as f64);
+        m.insert(MAX_LOAD_FACTOR * buckets, 0);
+        assert!(m.needs_resize());
+        assert_eq!(m.buckets.len(), buckets);
+
+        m.rehash();
+        assert_eq!(m.buckets.len(), 2 * buckets);
+        assert!(!m.needs_resize());
+        assert!(m.load_factor() < last);
+        assert_eq!(m.get(&7), Some(&7));
+    }
+
+    #[test]
+    fn cached_hashes_survive_mutation() {
+        let mut m: HashMap<u32, u32> = (0..5000).map(|i| (i, i)).collect();
+        assert!(m.verify_cached_hashes());
+        for v in m.values_mut() {
+            *v *= 2;
+        }
+        assert!(m.verify_cached_hashes());
+        *m.get_mut(&7).unwrap() += 1;
+        m.remove(&8);
+        m.shrink_to_fit();
+        assert!(m.verify_cached_hashes());
+        assert_eq!(m[&7], 15);
+        assert_eq!(m[&9], 18);
+        assert_eq!(m.values_mut().count(), 4999);
+    }
+
+    #[test]
+    fn retain_counts_removed() {
+        let mut m: HashMap<u32, u32> = (0..100).map(|i| (i, i)).collect();
+        assert_eq!(m.retain(|k, _| k % 2 == 0), 50);
+        assert_eq!(m.len(), 50);
+        assert!(m.contains_key(&0));
+        assert!(!m.contains_key(&1));
+        assert!(m.verify_cached_hashes());
+        assert_eq!(m.retain(|_, _| true), 0);
+    }
+
+    #[test]
+    fn insert_status_on_first_call() {
+        let mut m: HashMap<&str, u32> = HashMap::new();
+        let (v, inserted) = m.get_or_insert_with_status("resource", || 1);
+        assert!(inserted);
+        *v += 1;
+        let (v, inserted) = m.get_or_insert_with_status("resource", || unreachable!());
+        assert!(!inserted);
+        assert_eq!(*v, 2);
+        assert_eq!(m.len(), 1);
+    }
+
+    #[test]
+    fn prehashed_lookup_then_insert() {
+        let mut m: HashMap<String, u32> = HashMap::new();
+        let hash = m.prehash("Einar");
+        assert_eq!(m.get_prehashed(hash, "Einar"), None);
+        assert_eq!(m.insert_prehashed(hash, "Einar".to_string(), 25), None);
+        assert_eq!(m.get_prehashed(hash, "Einar"), m.get("Einar"));
+        assert_eq!(m.get_prehashed(hash, "Einar"), Some(&25));
+        assert_eq!(m.insert_prehashed(hash, "Einar".to_string(), 26), Some(25));
+        assert_eq!(m.len(), 1);
+        assert!(m.verify_cached_hashes());
+    }
+
+    #[test]
+    fn add_counters() {
+        let mut a: HashMap<&str, i64> = [("apple", 3), ("pear", 1)].iter().cloned().collect();
+        let b: HashMap<&str, i64> = [("apple", 2), ("plum", 5)].iter().cloned().collect();
+        a.add_assign_all(&b);
+        assert_eq!(a.len(), 3);
+        assert_eq!(a["apple"], 5);
+        assert_eq!(a["pear"], 1);
+        assert_eq!(a["plum"], 5);
+
+        let mut empty = HashMap::new();
+        empty.add_assign_all(&b);
+        assert_eq!(empty["plum"], 5);
+    }
+
+    #[test]
+    fn lookups_agree_on_empty_maps() {
+        let mut m: HashMap<u32, u32> = HashMap::new();
+        assert_eq!(m.get(&1), None);
+        assert_eq!(m.get_mut(&1), None);
+        assert!(!m.contains_key(&1));
+
+        for i in 0..10 {
+            m.insert(i, i);
+        }
+        m.clear();
+        assert!(m.is_empty());
+        assert!(!m.buckets.is_empty());
+        for i in 0..10 {
+            assert_eq!(m.get(&i), None);
+            assert_eq!(m.get_mut(&i), None);
+            assert_eq!(m.contains_key(&i), m.get(&i).is_some());
+        }
+    }
+
+    #[test]
+    fn clear_keeps_capacity_unlike_clear_and_shrink() {
+        let mut m: HashMap<u32, u32> = (0..5000).map(|i| (i, i)).collect();
+        let capacity = m.capacity();
+        m.clear();
+        assert!(m.is_empty());
+        assert_eq!(m.capacity(), capacity);
+
+        m.insert(1, 1);
+        m.clear_and_shrink();
+        assert!(m.is_empty());
+        assert_eq!(m.capacity(), 0);
+        assert_eq!(m.buckets.capacity(), 0);
+        assert_eq!(m.get(&1), None);
+        m.insert(2, 2);
+        assert_eq!(m[&2], 2);
+    }
+
+    #[test]
+    fn get_all_preserves_order() {
+        let m: HashMap<String, u32> = [("a", 1), ("b", 2), ("c", 3)]
+            .iter()
+            .map(|&(k, v)| (k.to_string(), v))
+            .collect();
+        let requested: Vec<&str> = vec!["c", "x", "a", "a", "y", "b"];
+        assert_eq!(
+            m.get_all(requested),
+            vec![Some(&3), None, Some(&1), Some(&1), None, Some(&2)]
+        );
+        assert!(m.get_all(Vec::<&str>::new()).is_empty());
+    }
+
+    #[test]
+    fn new_map_does_not_allocate() {
+        let mut m: HashMap<String, u32> = HashMap::new();
+        assert_eq!(m.buckets.capacity(), 0);
+        assert!(!m.contains_key("a"));
+        assert_eq!(m.get("a"), None);
+        assert_eq!(m.get_mut("a"), None);
+        assert_eq!(m.remove("a"), None);
+        assert_eq!(m.len(), 0);
+        assert!(m.is_empty());
+        assert_eq!(m.iter().count(), 0);
+        assert_eq!(m.buckets.capacity(), 0);
+
+        let d: HashMap<u32, u32> = HashMap::default();
+        assert_eq!(d.buckets.capacity(), 0);
+    }
+
+    #[test]
+    fn replace_swaps_key() {
+        #[derive(Debug)]
+        struct Tagged {
+            id: u32,
+            tag: &'static str,
+        }
+        impl PartialEq for Tagged {
+            fn eq(&self, other: &Self) -> bool {
+                self.id == other.id
+            }
+        }
+        impl Eq for Tagged {}
+        impl std::hash::Hash for Tagged {
+            fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+                self.id.hash(state);
+            }
+        }
+
+        let mut m = HashMap::new();
+        assert!(m.replace(Tagged { id: 1, tag: "old" }, 1).is_none());
+        m.insert(
+            Tagged {
+                id: 1,
+                tag: "ignored",
+            },
+            2,
+        );
+        assert_eq!(m.keys().next().unwrap().tag, "old");
+
+        let (old_key, old_value) = m.replace(Tagged { id: 1, tag: "new" }, 3).unwrap();
+        assert_eq!(old_key.tag, "old");
+        assert_eq!(old_value, 2);
+        assert_eq!(m.len(), 1);
+        assert_eq!(m.keys().next().unwrap().tag, "new");
+        assert_eq!(m[&Tagged { id: 1, tag: "" }], 3);
+    }
+
+    #[test]
+    fn retain_map_transforms_or_drops() {
+        let mut m: HashMap<u32, u32> = (0..100).map(|i| (i, i)).collect();
+        let removed = m.retain_map(|_, v| if v % 2 == 0 { Some(2 * v) } else { None });
+        assert_eq!(removed, 50);
+        assert_eq!(m.len(), 50);
+        for i in 0..100 {
+            if i % 2 == 0 {
+                assert_eq!(m[&i], 2 * i);
+            } else {
+                assert!(!m.contains_key(&i));
+            }
+        }
+        assert!(m.verify_cached_hashes());
+    }
+
+    #[test]
+    fn singleton_map() {
+        let m = HashMap::singleton(1, "a");
+        assert_eq!(m.len(), 1);
+        assert_eq!(m.get(&1), Some(&"a"));
+        assert_eq!(m.buckets.len(), MIN_BUCKETS);
+    }
+
+    #[test]
+    fn recount_repairs_drift() {
+        let mut m: HashMap<u32, u32> = (0..100).map(|i| (i, i)).collect();
+        assert_eq!(m.recount(), 100);
+        m.num_items = 250;
+        assert_eq!(m.recount(), 100);
+        assert_eq!(m.len(), 100);
+        m.num_items = 3;
+        assert_eq!(m.recount(), 100);
+
+        let mut empty: HashMap<u32, u32> = HashMap::new();
+        assert_eq!(empty.recount(), 0);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "item count out of sync")]
+    fn remove_with_desynced_count_panics() {
+        let mut m: HashMap<u32, u32> = (0..3).map(|i| (i, i)).collect();
+        m.num_items = 0;
+        m.remove(&1);
+    }
+
+    #[test]
+    fn update_matching_values() {
+        let mut m: HashMap<u32, u32> = (0..10).map(|i| (i, 0)).collect();
+        assert_eq!(m.update_where(|&k, _| k > 6, |v| *v += 1), 3);
+        for i in 0..10 {
+            assert_eq!(m[&i], if i > 6 { 1 } else { 0 });
+        }
+        assert_eq!(m.update_where(|_, &v| v > 5, |v| *v += 1), 0);
+    }
+
+    #[test]
+    fn no_resize_oscillation_around_watermarks() {
+        let mut m: HashMap<usize, usize> = HashMap::new();
+        let grown = 2 * DEFAULT_BUCKETS;
+        let mut n = 0;
+        while m.buckets.len() < grown {
+            m.insert(n, n);
+            n += 1;
+        }
+        // fill up to the grow watermark
+        while n < MAX_LOAD_FACTOR * grown {
+            m.insert(n, n);
+            n += 1;
+        }
+        for _ in 0..100 {
+            m.insert(n, n);
+            m.remove(&n);
+            assert_eq!(m.buckets.len(), grown);
+        }
+
+        // empty down to the shrink watermark
+        while m.len() * MIN_LOAD_INVERSE >= grown {
+            n -= 1;
+            m.remove(&n);
+        }
+        assert_eq!(m.buckets.len(), DEFAULT_BUCKETS);
+        for _ in 0..100 {
+            m.insert(n, n);
+            assert_eq!(m.buckets.len(), DEFAULT_BUCKETS);
+            m.remove(&n);
+            assert_eq!(m.buckets.len(), DEFAULT_BUCKETS);
+        }
+        for i in 0..n {
+            assert_eq!(m.get(&i), Some(&i));
+        }
+    }
+
+    #[test]
+    fn build_from_keys() {
+        let m = HashMap::from_keys(vec!["a", "bb", "ccc"], |k| k.len());
+        assert_eq!(m.len(), 3);
+        assert_eq!(m["a"], 1);
+        assert_eq!(m["bb"], 2);
+        assert_eq!(m["ccc"], 3);
+    }
+
+    #[test]
+    fn rebuild_with_other_hasher() {
+        let mut m = HashMap::with_hasher(RandomState::new());
+        for i in 0..1000u32 {
+            m.insert(i, i * 2);
+        }
+        let fnv: HashMap<u32, u32, BuildHasherDefault<Fnv>> = m.into_hasher(Default::default());
+        assert_eq!(fnv.len(), 1000);
+        for i in 0..1000u32 {
+            assert_eq!(fnv.get(&i), Some(&(i * 2)));
+        }
+        assert!(fnv.verify_cached_hashes());
+    }
+
+    #[test]
+    fn pop_from_different_offsets() {
+        let mut m: HashMap<u64, u64> = (0..100).map(|i| (i, i)).collect();
+        let half = m.buckets.len() / 2;
+        let first = m.pop_from(0).unwrap();
+        let second = m.pop_from(half).unwrap();
+        assert_ne!(first, second);
+        assert!(m.bucket_index(m.hash_key(&first.0)) < half);
+        assert!(m.bucket_index(m.hash_key(&second.0)) >= half);
+        assert_eq!(m.len(), 98);
+        assert!(!m.contains_key(&first.0));
+        while m.pop_from(half).is_some() {}
+        assert!(m.is_empty());
+        assert_eq!(m.recount(), 0);
+    }
+
+    #[test]
+    fn hash_ignores_insertion_order() {
+        let forward: HashMap<u32, u32> = (0..500).map(|i| (i, i * 3)).collect();
+        let backward: HashMap<u32, u32> = (0..500).rev().map(|i| (i, i * 3)).collect();
+        let mut compact = HashMap::with_capacity(500);
+        for (&k, &v) in backward.iter() {
+            compact.insert(k, v);
+        }
+        assert_ne!(compact.buckets.len(), forward.buckets.len());
+        assert!(forward == backward);
+        assert!(forward == compact);
+        let hash = |m: &HashMap<u32, u32>| DefaultHashBuilder::default().hash_one(m);
+        assert_eq!(hash(&forward), hash(&backward));
+        assert_eq!(hash(&forward), hash(&compact));
+        let mut changed = backward;
+        changed.insert(0, 1);
+        assert!(forward != changed);
+        assert_ne!(hash(&forward), hash(&changed));
+
+        let mut nested = HashMap::new();
+        nested.insert(forward, "forward");
+        assert_eq!(
+            nested.get(&(0..500).map(|i| (i, i * 3)).collect()),
+            Some(&"forward")
+        );
+    }
+
+    #[test]
+    fn cloned_or_default_does_not_insert() {
+        let mut m: HashMap<&str, Vec<u32>> = HashMap::new();
+        m.insert("a", vec![1, 2]);
+        assert_eq!(m.get_cloned_or_default("a"), vec![1, 2]);
+        assert_eq!(m.get_cloned_or_default("b"), Vec::<u32>::new());
+        assert_eq!(m.len(), 1);
+        assert!(!m.contains_key("b"));
+    }
+
+    #[test]
+    fn lookup_by_predicate() {
+        let mut m: HashMap<(u32, &str), u32> = HashMap::new();
+        for (i, name) in ["zero", "one", "two", "three"].iter().enumerate() {
+            m.insert((i as u32, name), i as u32);
+        }
+        let hash = m.prehash(&(2, "two"));
+        assert_eq!(m.get_by(hash, |k| k.1 == "two"), Some(&2));
+        assert_eq!(m.get_by(hash, |k| k.1 == "four"), None);
+        assert_eq!(HashMap::<u32, u32>::new().get_by(hash, |_| true), None);
+    }
+
+    #[test]
+    fn resize_to_exact_bucket_count() {
+        let mut m: HashMap<u64, u64> = (0..1000).map(|i| (i, i)).collect();
+        m.resize_to(4096);
+        assert_eq!(m.buckets.len(), 4096);
+        assert!((0..1000).all(|i| m.get(&i) == Some(&i)));
+        assert!(m.verify_cached_hashes());
+        m.resize_to(3000);
+        assert_eq!(m.buckets.len(), 4096);
+        m.resize_to(100);
+        assert_eq!(m.buckets.len(), 128);
+        assert!((0..1000).all(|i| m.get(&i) == Some(&i)));
+    }
+
+    #[test]
+    fn contains_all_or_any() {
+        let m: HashMap<u32, u32> = (0..10).map(|i| (i, i)).collect();
+        assert!(m.contains_all(&[1, 5, 9]));
+        assert!(!m.contains_all(&[1, 50, 9]));
+        assert!(!m.contains_all(&[10, 11]));
+        assert!(m.contains_all(&[]));
+
+        assert!(m.contains_any(&[1, 5, 9]));
+        assert!(m.contains_any(&[10, 50, 9]));
+        assert!(!m.contains_any(&[10, 11]));
+        assert!(!m.contains_any(&[]));
+    }
+
+    #[test]
+    fn extend_keeps_present_values() {
+        let mut settings: HashMap<&str, u32> = HashMap::new();
+        settings.insert("width", 80);
+        settings.insert("depth", 3);
+        let defaults = [("width", 100), ("height", 40), ("depth", 1), ("margin", 2)];
+        assert_eq!(settings.extend_absent(defaults), 2);
+        assert_eq!(settings.len(), 4);
+        assert_eq!(settings.get("width"), Some(&80));
+        assert_eq!(settings.get("depth"), Some(&3));
+        assert_eq!(settings.get("height"), Some(&40));
+        assert_eq!(settings.get("margin"), Some(&2));
+        assert_eq!(settings.extend_absent(defaults), 0);
+    }
+
+    #[test]
+    fn scale_every_value() {
+        let mut m: HashMap<u32, f64> = (0..20).map(|i| (i, i as f64 * 0.5)).collect();
+        m.scale_values(2.0);
+        assert_eq!(m.len(), 20);
+        for (&k, &v) in &m {
+            assert_eq!(v, k as f64);
+        }
+    }
+
+    #[test]
+    fn extreme_values() {
+        let scores: HashMap<&str, u32> = [("ann", 7), ("bob", 12), ("cid", 3), ("dee", 9)]
+            .iter()
+            .cloned()
+            .collect();
+        assert_eq!(scores.max_by_value(), Some((&"bob", &12)));
+        assert_eq!(scores.min_by_value(), Some((&"cid", &3)));
+        let empty: HashMap<&str, u32> = HashMap::new();
+        assert_eq!(empty.max_by_value(), None);
+        assert_eq!(empty.min_by_value(), None);
+    }
+
+    #[test]
+    fn fold_and_sum_values() {
+        let m: HashMap<u32, u64> = (0..100).map(|i| (i, u64::from(i) * 3)).collect();
+        let mut manual = 0;
+        for (_, &v) in &m {
+            manual += v;
+        }
+        assert_eq!(m.sum_values(), manual);
+        assert_eq!(HashMap::<u32, u64>::new().sum_values(), 0);
+
+        let words: HashMap<u32, &str> = [(1, "ab"), (2, "cd"), (3, "ef")].iter().cloned().collect();
+        let joined = words.fold_values(String::new(), |acc, v| acc + v);
+        assert_eq!(joined.len(), 6);
+        assert!(words.values().all(|v| joined.contains(v)));
+    }
+
+    #[test]
+    fn seeds_spread_keys_differently() {
+        let mut first = HashMap::with_seed(1);
+        let mut second = HashMap::with_seed(2);
+        for i in 0..100u32 {
+            first.insert(i, i);
+            second.insert(i, i);
+        }
+        let moved = (0..100u32)
+            .filter(|i| {
+                first.bucket_index(first.hash_key(i)) != second.bucket_index(second.hash_key(i))
+            })
+            .count();
+        assert!(moved > 90);
+        assert!((0..100).all(|i| first.get(&i) == Some(&i) && second.get(&i) == Some(&i)));
+        assert!(first.verify_cached_hashes());
+
+        let parts = first.split(3);
+        for part in &parts {
+            assert!(part.verify_cached_hashes());
+        }
+        assert_eq!(parts.iter().map(|part| part.len()).sum::<usize>(), 100);
+    }
+
+    #[test]
+    fn reserve_for_half_load() {
+        let mut m = HashMap::new();
+        m.reserve_for_load(1000, 0.5);
+        let bucket_count = m.buckets.len();
+        for i in 0..1000u32 {
+            m.insert(i, i);
+        }
+        assert_eq!(m.buckets.len(), bucket_count);
+        assert!((m.load_factor() - 0.5).abs() < 0.05);
+        assert!(m.load_factor() <= 0.5);
+    }
+
+    #[test]
+    fn filter_entries_above_threshold() {
+        let m: HashMap<u32, u32> = (0..20).map(|i| (i, i * 10)).collect();
+        let mut above: Vec<(u32, u32)> = m
+            .filter_entries(|_, &v| v > 150)
+            .map(|(&k, &v)| (k, v))
+            .collect();
+        above.sort_unstable();
+        assert_eq!(above, vec![(16, 160), (17, 170), (18, 180), (19, 190)]);
+        assert_eq!(m.filter_entries(|_, _| false).count(), 0);
+    }
+
+    #[test]
+    fn insert_all_reports_displaced() {
+        let mut m: HashMap<&str, u32> = HashMap::new();
+        m.insert("a", 1);
+        m.insert("c", 3);
+        let displaced = m.insert_all(vec![("a", 10), ("b", 20), ("c", 30), ("a", 100)]);
+        assert_eq!(displaced, vec![("a", 1), ("c", 3), ("a", 10)]);
+        assert_eq!(m.len(), 3);
+        assert_eq!(m.get("a"), Some(&100));
+        assert_eq!(m.get("b"), Some(&20));
+        assert_eq!(m.get("c"), Some(&30));
+    }
+
+    #[test]
+    fn into_ordered_map() {
+        let mut m = HashMap::new();
+        for i in [42u32, 7, 19, 3, 88, 1, 56] {
+            m.insert(i, i * 2);
+        }
+        let ordered = m.into_btree_map();
+        let keys: Vec<u32> = ordered.keys().cloned().collect();
+        assert_eq!(keys, vec![1, 3, 7, 19, 42, 56, 88]);
+        assert!(ordered.iter().all(|(k, v)| *v == k * 2));
+    }
+
+    #[test]
+    fn doubling_hashes_no_key() {
+        let mut m = HashMap::new();
+        for i in 0..3 * DEFAULT_BUCKETS as u32 {
+            m.insert(Counted(i), i);
+        }
+        assert_eq!(Counted::take_hash_calls(), 3 * DEFAULT_BUCKETS);
+        m.rehash();
+        assert_eq!(m.buckets.len(), 2 * DEFAULT_BUCKETS);
+        assert_eq!(Counted::take_hash_calls(), 0);
+        m.insert(Counted(u32::MAX), 0);
+        m.rehash();
+        assert_eq!(Counted::take_hash_calls(), 1);
+        assert!(m.verify_cached_hashes());
+        assert_eq!(m.len(), 3 * DEFAULT_BUCKETS + 1);
+        assert!((0..3 * DEFAULT_BUCKETS as u32).all(|i| m.get(&Counted(i)) == Some(&i)));
+    }
+
+    #[test]
+    fn swap_values_by_key() {
+        let mut m: HashMap<&str, u32> = HashMap::new();
+        m.insert("a", 1);
+        m.insert("b", 2);
+        assert!(m.swap("a", "b"));
+        assert_eq!((m["a"], m["b"]), (2, 1));
+        assert!(m.swap("a", "a"));
+        assert_eq!(m["a"], 2);
+        assert!(!m.swap("a", "z"));
+        assert!(!m.swap("z", "b"));
+        assert_eq!((m["a"], m["b"]), (2, 1));
+        assert_eq!(m.len(), 2);
+
+        let mut clustered = HashMap::new();
+        for i in 0..4 {
+            clustered.insert(Colliding(i), i);
+        }
+        assert!(clustered.swap(&Colliding(3), &Colliding(1)));
+        assert_eq!(clustered[&Colliding(1)], 3);
+        assert_eq!(clustered[&Colliding(3)], 1);
+    }
+
+    #[test]
+    fn collect_keeping_max_value() {
+        let pairs = vec![("a", 3), ("b", 1), ("a", 7), ("c", 2), ("b", 5), ("a", 4)];
+        let m = HashMap::from_iter_with(pairs, std::cmp::max);
+        assert_eq!(m.len(), 3);
+        assert_eq!(m["a"], 7);
+        assert_eq!(m["b"], 5);
+        assert_eq!(m["c"], 2);
+        assert!(m.verify_cached_hashes());
+    }
+
+    #[test]
+    fn mutate_many_disjoint_keys() {
+        let mut m: HashMap<u32, u32> = (0..100).map(|i| (i, i)).collect();
+        let mut values = m.get_mut_many(&[&42, &7, &1000, &99]).unwrap();
+        assert!(values[2].is_none());
+        for value in values.iter_mut().flatten() {
+            **value += 1000;
+        }
+        assert_eq!(values[0].as_deref(), Some(&1042));
+        assert_eq!((m[&42], m[&7], m[&99], m[&8]), (1042, 1007, 1099, 8));
+
+        let mut clustered = HashMap::new();
+        for i in 0..5 {
+            clustered.insert(Colliding(i), i);
+        }
+        let values = clustered
+            .get_mut_many(&[&Colliding(4), &Colliding(0), &Colliding(2)])
+            .unwrap();
+        let got: Vec<u32> = values.into_iter().map(|v| *v.unwrap()).collect();
+        assert_eq!(got, vec![4, 0, 2]);
+    }
+
+    #[test]
+    fn mutate_many_rejects_duplicates() {
+        let mut m: HashMap<u32, u32> = (0..100).map(|i| (i, i)).collect();
+        let err = m.get_mut_many(&[&1, &2, &3, &2]).unwrap_err();
+        assert_eq!(
+            err,
+            crate::DuplicateKeyError {
+                first: 1,
+                second: 3
+            }
+        );
+        assert!(m.get_mut_many(&[&500, &500]).is_err());
+        assert_eq!(m.len(), 100);
+
+        // equal hashes alone are not repeats
+        let mut clustered: HashMap<Colliding, u32> = HashMap::new();
+        assert!(clustered
+            .get_mut_many(&[&Colliding(1), &Colliding(2)])
+            .is_ok());
+        let err = clustered
+            .get_mut_many(&[&Colliding(1), &Colliding(2), &Colliding(3), &Colliding(2)])
+            .unwrap_err();
+        assert_eq!((err.first, err.second), (1, 3));
+    }
+
+    #[test]
+    fn fill_squares() {
+        let mut squares = HashMap::new();
+        squares.fill_range(0..100, |k| k * k);
+        assert_eq!(squares.len(), 100);
+        assert_eq!(squares.buckets.len(), capacity_for(100));
+        assert_eq!(squares[&0], 0);
+        assert_eq!(squares[&7], 49);
+        assert_eq!(squares[&99], 9801);
+        assert!(!squares.contains_key(&100));
+    }
+
+    #[test]
+    fn drop_zero_counts() {
+        let mut counts: HashMap<&str, u32> = HashMap::new();
+        for (word, count) in [("a", 0), ("b", 3), ("c", 0), ("d", 1)].iter() {
+            counts.insert(word, *count);
+        }
+        assert_eq!(counts.retain_values(|&count| count > 0), 2);
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts.get("b"), Some(&3));
+        assert_eq!(counts.get("d"), Some(&1));
+        assert!(!counts.contains_key("a") && !counts.contains_key("c"));
+    }
+
+    #[test]
+    fn resize_into_recycled_scratch() {
+        let mut m: HashMap<u32, u32> = (0..1000).map(|i| (i, i)).collect();
+        let old = m.resize_into(4096, Vec::new());
+        assert_eq!(old.len(), DEFAULT_BUCKETS);
+        assert!(old.iter().all(|bkt| bkt.items.is_empty()));
+        assert_eq!(m.buckets.len(), 4096);
+        assert!((0..1000).all(|i| m.get(&i) == Some(&i)));
+
+        let larger = m.resize_into(DEFAULT_BUCKETS, old);
+        assert_eq!(larger.len(), 4096);
+        assert_eq!(m.buckets.len(), DEFAULT_BUCKETS);
+        assert!(m.buckets.iter().any(|bkt| bkt.items.capacity() > 0));
+        assert!((0..1000).all(|i| m.get(&i) == Some(&i)));
+        assert!(m.verify_cached_hashes());
+        assert_eq!(m.recount(), 1000);
+    }
+
+    #[test]
+    fn resize_into_round_trip_through_unallocated_map() {
+        let mut m: HashMap<u32, u32> = HashMap::new();
+        m.insert(1, 1);
+        m.clear_and_shrink();
+        let unallocated = m.resize_into(DEFAULT_BUCKETS, Vec::with_capacity(DEFAULT_BUCKETS));
+        assert!(unallocated.is_empty());
+        assert_eq!(m.buckets.len(), DEFAULT_BUCKETS);
+        m.insert(2, 2);
+
+        let scratch = m.resize_into(DEFAULT_BUCKETS, unallocated);
+        assert_eq!(scratch.len(), DEFAULT_BUCKETS);
+        assert_eq!(m.buckets.len(), DEFAULT_BUCKETS);
+        let scratch = m.resize_into(3000, scratch);
+        assert_eq!(scratch.len(), DEFAULT_BUCKETS);
+        assert_eq!(m.buckets.len(), 4096);
+        assert_eq!(m.get(&2), Some(&2));
+        assert_eq!(m.recount(), 1);
+    }
+
+    #[test]
+    fn count_even_keys() {
+        let m: HashMap<u32, u32> = (0..100).map(|i| (i, i * 3)).collect();
+        assert_eq!(m.count_where(|k, _| k % 2 == 0), 50);
+        assert_eq!(m.count_where(|_, &v| v >= 150), 50);
+        assert_eq!(HashMap::<u32, u32>::new().count_where(|_, _| true), 0);
+    }
+
+    #[test]
+    fn from_columns() {
+        let keys = ["a", "b", "a", "c"];
+        let values = [1, 2, 3, 4];
+        let m = HashMap::from_slices(&keys, &values);
+        assert_eq!(m.len(), 3);
+        assert_eq!(m.get("a"), Some(&3));
+        assert_eq!(m.get("b"), Some(&2));
+        assert_eq!(m.get("c"), Some(&4));
+        assert!(HashMap::<u32, u32>::from_slices(&[], &[]).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "different lengths")]
+    fn from_columns_length_mismatch() {
+        HashMap::from_slices(&[1, 2, 3], &["one", "two"]);
+    }
+
+    #[test]
+    fn take_replace_only_present_keys() {
+        let mut m: HashMap<String, Vec<u32>> = HashMap::new();
+        m.insert("batch".to_string(), vec![1, 2, 3]);
+        assert_eq!(m.take_replace("batch", Vec::new()), Some(vec![1, 2, 3]));
+        assert_eq!(m.get("batch"), Some(&Vec::new()));
+        assert_eq!(m.take_replace("missing", vec![4]), None);
+        assert!(!m.contains_key("missing"));
+        assert_eq!(m.len(), 1);
+    }
+
+    #[test]
+    fn drain_above_threshold() {
+        let mut m: HashMap<u32, u32> = (0..100).map(|i| (i, i * 10)).collect();
+        let mut drained = m.drain_where(|_, &v| v > 900);
+        drained.sort_unstable();
+        assert_eq!(drained, (91..100).map(|i| (i, i * 10)).collect::<Vec<_>>());
+        assert_eq!(m.len(), 91);
+        assert_eq!(m.recount(), 91);
+        assert!((0..91).all(|i| m.contains_key(&i)));
+        assert!(m.drain_where(|_, &v| v > 900).is_empty());
+        assert_eq!(m.len(), 91);
+    }
+
+    #[test]
+    fn merge_policies() {
+        let pairs = [("a", 1), ("b", 2)];
+        let other = [("b", 20), ("c", 30)];
+
+        let mut kept: HashMap<&str, u32> = pairs.iter().cloned().collect();
+        let from: HashMap<&str, u32> = other.iter().cloned().collect();
+        kept.extend_preferring_existing(from);
+        assert_eq!(kept.len(), 3);
+        assert_eq!(kept.get("a"), Some(&1));
+        assert_eq!(kept.get("b"), Some(&2));
+        assert_eq!(kept.get("c"), Some(&30));
+
+        let mut counted: HashMap<&str, u32> = pairs.iter().cloned().collect();
+        let from: HashMap<&str, u32> = other.iter().cloned().collect();
+        assert_eq!(counted.extend_absent(from), 1);
+        assert_eq!(counted, kept);
+
+        let mut overwritten: HashMap<&str, u32> = pairs.iter().cloned().collect();
+        let from: HashMap<&str, u32> = other.iter().cloned().collect();
+        overwritten.extend(from);
+        assert_eq!(overwritten.len(), 3);
+        assert_eq!(overwritten.get("a"), Some(&1));
+        assert_eq!(overwritten.get("b"), Some(&20));
+        assert_eq!(overwritten.get("c"), Some(&30));
+    }
+
+    #[test]
+    fn prefault_every_bucket() {
+        let mut m: HashMap<u32, u32> = HashMap::new();
+        m.prefault(4);
+        assert_eq!(m.buckets.len(), DEFAULT_BUCKETS);
+        assert!(m
+            .buckets
+            .iter()
+            .all(|bkt| bkt.items.capacity() >= 4 && bkt.hashes.capacity() >= 4));
+        let before: Vec<*const (u32, u32)> =
+            m.buckets.iter().map(|bkt| bkt.items.as_ptr()).collect();
+        for i in 0..DEFAULT_BUCKETS as u32 {
+            m.insert(i, i);
+        }
+        assert_eq!(m.buckets.len(), DEFAULT_BUCKETS);
+        for (bkt, &ptr) in m.buckets.iter().zip(&before) {
+            if bkt.items.len() <= 4 {
+                assert_eq!(bkt.items.as_ptr(), ptr);
+            }
+        }
+    }
+
+    #[test]
+    fn colliding_keys_share_bucket_index() {
+        let mut m: HashMap<u32, u32, BuildHasherDefault<Identity>> = HashMap::default();
+        assert_eq!(m.bucket_index_of(&3), None);
+        m.insert(3, 0);
+        let n = m.buckets.len() as u32;
+        assert_eq!(m.bucket_index_of(&3), Some(3));
+        assert_eq!(m.bucket_index_of(&(3 + n)), Some(3));
+        assert_eq!(m.bucket_index_of(&4), Some(4));
+
+        let mut colliding = HashMap::new();
+        colliding.insert(Colliding(1), ());
+        assert_eq!(
+            colliding.bucket_index_of(&Colliding(1)),
+            colliding.bucket_index_of(&Colliding(2))
+        );
+    }
+
+    #[test]
+    fn extend_from_references() {
+        let source: HashMap<i32, i32> = (0..100).map(|i| (i, -i)).collect();
+        let mut m: HashMap<i32, i32> = (90..110).map(|i| (i, 0)).collect();
+        m.extend(&source);
+        assert_eq!(m.len(), 110);
+        assert!((0..100).all(|i| m.get(&i) == Some(&-i)));
+        assert!((100..110).all(|i| m.get(&i) == Some(&0)));
+        assert_eq!(source.len(), 100);
+    }
+
+    #[test]
+    fn clone_of_existing_or_inserted() {
+        let mut m: HashMap<&str, String> = HashMap::new();
+        m.insert("present", "cached".to_string());
+        let mut value = m.get_or_insert_cloned("present", "fresh".to_string());
+        assert_eq!(value, "cached");
+        value.push('!');
+        assert_eq!(m["present"], "cached");
+
+        assert_eq!(
+            m.get_or_insert_cloned("absent", "fresh".to_string()),
+            "fresh"
+        );
+        assert_eq!(m["absent"], "fresh");
+        assert_eq!(m.len(), 2);
+    }
+
+    #[test]
+    fn clear_some_buckets() {
+        let mut m: HashMap<u32, u32, BuildHasherDefault<Identity>> = HashMap::default();
+        let n = DEFAULT_BUCKETS as u32;
+        for i in 0..2 * n {
+            m.insert(i, i);
+        }
+        assert_eq!(m.buckets.len(), DEFAULT_BUCKETS);
+        assert_eq!(m.clear_bucket_range(100..200), 200);
+        assert_eq!(m.len(), 2 * DEFAULT_BUCKETS - 200);
+        assert_eq!(m.recount(), 2 * DEFAULT_BUCKETS - 200);
+        assert!((0..2 * n).all(|i| m.contains_key(&i) != (100..200).contains(&(i % n))));
+        assert!(m.buckets[100..200]
+            .iter()
+            .all(|bkt| bkt.items.capacity() == 0));
+        assert_eq!(m.buckets.len(), DEFAULT_BUCKETS);
+
+        assert_eq!(m.clear_bucket_range(150..250), 100);
+        assert_eq!(m.clear_bucket_range(DEFAULT_BUCKETS - 1..usize::MAX), 2);
+        assert_eq!(m.recount(), m.len());
+    }
+
+    #[test]
+    fn explicit_sizing_survives_removals() {
+        let mut m: HashMap<u32, u32> = HashMap::with_capacity(300_000);
+        let capacity = m.capacity();
+        for i in 0..10 {
+            m.insert(i, i);
+        }
+        m.remove(&0);
+        assert_eq!(m.capacity(), capacity);
+
+        let mut m: HashMap<u32, u32> = HashMap::new();
+        m.resize_to(1 << 16);
+        m.insert(1, 1);
+        m.remove(&1);
+        assert_eq!(m.buckets.len(), 1 << 16);
+
+        m.shrink_to_fit();
+        let shrunk = m.buckets.len();
+        assert!(shrunk < DEFAULT_BUCKETS);
+        for i in 0..10_000 {
+            m.insert(i, i);
+        }
+        for i in 0..10_000 {
+            m.remove(&i);
+        }
+        assert_eq!(m.buckets.len(), DEFAULT_BUCKETS);
+    }
+
+    fn occupancy_in_sync<K: Eq + std::hash::Hash, V, S>(m: &HashMap<K, V, S>) -> bool {
+        m.occupied.len() == m.buckets.len().div_ceil(64)
+            && m.buckets.iter().enumerate().all(|(idx, bkt)| {
+                (m.occupied[idx / 64] >> (idx % 64) & 1 == 1) != bkt.items.is_empty()
+            })
+    }
+
+    #[test]
+    fn occupancy_follows_mutations() {
+        let mut m: HashMap<u32, u32> = HashMap::new();
+        assert!(occupancy_in_sync(&m));
+        for i in 0..5000 {
+            m.insert(i, i);
+        }
+        assert!(occupancy_in_sync(&m));
+        for i in 0..4000 {
+            m.remove(&i);
+        }
+        assert!(occupancy_in_sync(&m));
+        m.retain(|k, _| k % 3 == 0);
+        assert!(occupancy_in_sync(&m));
+        m.drain_where(|k, _| k % 2 == 0);
+        assert!(occupancy_in_sync(&m));
+        {
+            let mut cursor = m.cursor_mut();
+            while let Some((k, _)) = cursor.next() {
+                if k % 5 == 0 {
+                    cursor.remove_current();
+                }
+            }
+        }
+        assert!(occupancy_in_sync(&m));
+        m.shrink_to_fit();
+        assert!(occupancy_in_sync(&m));
+        assert_eq!(m.iter().count(), m.len());
+        m.clear();
+        assert!(occupancy_in_sync(&m));
+        assert_eq!(m.iter().count(), 0);
+        m.insert(7, 7);
+        assert_eq!(m.iter().collect::<Vec<_>>(), vec![(&7, &7)]);
+        m.clear_and_shrink();
+        assert!(occupancy_in_sync(&m));
+        m.insert(8, 8);
+        assert!(occupancy_in_sync(&m));
+    }
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn maps_and_iterators_are_send_sync() {
+        assert_send_sync::<HashMap<u64, u64>>();
+        assert_send_sync::<HashMap<String, Vec<u8>>>();
+        assert_send_sync::<crate::HashMapIterator<'_, u64, u64>>();
+        assert_send_sync::<crate::Entries<'_, u64, u64>>();
+        assert_send_sync::<crate::Keys<'_, u64, u64>>();
+        assert_send_sync::<crate::Values<'_, u64, u64>>();
+        assert_send_sync::<crate::ValuesMut<'_, u64, u64>>();
+        assert_send_sync::<crate::CappedMap<u64, u64>>();
+    }
+
+    #[test]
+    fn iter_on_bucket() {
+        let mut bkt: Bucket<u64, String> = Bucket::new();
+        assert!(bkt.insert(3, 3, "hi".to_string()).is_none());
+        assert!(bkt.insert(2, 2, "hi".to_string()).is_none());
+        assert!(bkt.insert(1, 1, "hi".to_string()).is_none());
+        let mut nitems = 0;
+        for (k, v) in bkt.into_iter() {
+            nitems += 1;
+            assert!(k <= &3);
+            assert_eq!(v, &"hi".to_string());
+        }
+        assert_eq!(nitems, 3);
+    }
+
+    #[test]
+    fn iter_on_hashmap() {
+        let mut m: HashMap<u64, String> = HashMap::new();
+        assert_eq!(m.insert(3, "hi".to_string()), None);
+        assert_eq!(m.insert(4, "hi".to_string()), None);
+        assert_eq!(m.insert(5, "hi".to_string()), None);
+        assert_eq!(m.insert(0, "hi".to_string()), None);
         let mut items = 0;
-        for (k, v) in m.into_iter() {
+        for (k, v) in (&m).into_iter() {
             items += 1;
             assert!(k <= &5);
             assert_eq!(v, &"hi".to_string());
@@ -268,6 +2705,237 @@ mod tests {
         assert_eq!(items, 4);
     }
 
+    #[test]
+    fn iter_counts_every_entry() {
+        let m: HashMap<u64, u64> = (0..100).map(|i| (i, i * i)).collect();
+        assert_eq!(m.iter().count(), m.len());
+        assert_eq!(m.iter().filter(|(k, _)| *k % 2 == 0).count(), 50);
+    }
+
+    #[test]
+    fn capacity_for_rounds_to_power_of_two() {
+        assert_eq!(capacity_for(0), 0);
+        assert_eq!(capacity_for(1), MIN_BUCKETS);
+        for n in [1, 7, 25, 100, 1000, 3073, 10_000] {
+            let buckets = capacity_for(n);
+            assert!(buckets.is_power_of_two());
+            assert!(buckets * MAX_LOAD_FACTOR >= n);
+        }
+    }
+
+    #[test]
+    fn growth_stops_before_overflow() {
+        assert_eq!(grown_size(0), Some(DEFAULT_BUCKETS));
+        assert_eq!(grown_size(DEFAULT_BUCKETS), Some(2 * DEFAULT_BUCKETS));
+        let largest = 1usize << (usize::BITS - 1);
+        assert_eq!(grown_size(largest / 2), Some(largest));
+        assert_eq!(grown_size(largest), None);
+    }
+
+    #[test]
+    fn index_within_small_bucket_counts() {
+        for &bucket_count in &[1, 2, 8, 1024] {
+            for &hash in &[0, 1, 7, 8, 1023, 0xdead_beef, u32::MAX as u64, u64::MAX] {
+                let idx = index_for(hash, bucket_count);
+                assert!(idx < bucket_count);
+                assert_eq!(idx as u64, hash % bucket_count as u64);
+                // only the low bits matter, as on a 32-bit target
+                assert_eq!(idx, index_for(hash as u32 as u64, bucket_count));
+            }
+        }
+        assert_eq!(index_for(u64::MAX, 8), 7);
+        assert_eq!(index_for(1 << 40, 1024), 0);
+    }
+
+    #[test]
+    fn with_capacity_holds_items_without_resizing() {
+        for n in [1, 100, 5000] {
+            let mut m: HashMap<usize, usize> = HashMap::with_capacity(n);
+            let buckets = m.buckets.len();
+            assert_eq!(buckets, capacity_for(n));
+            assert!(buckets.is_power_of_two());
+            assert!(m.capacity() >= n);
+            for i in 0..n {
+                m.insert(i, i);
+            }
+            assert_eq!(m.buckets.len(), buckets);
+        }
+        let empty: HashMap<u64, u64> = HashMap::with_capacity(0);
+        assert_eq!(empty.buckets.capacity(), 0);
+    }
+
+    #[test]
+    fn histogram_with_default() {
+        let mut hist: HashMap<u32, usize> = HashMap::new();
+        for i in 0..1000 {
+            *hist.get_mut_or_insert_default(i % 7) += 1;
+        }
+        assert_eq!(hist.len(), 7);
+        for r in 0..6 {
+            assert_eq!(hist[&r], 143);
+        }
+        assert_eq!(hist[&6], 142);
+    }
+
+    #[test]
+    fn iter_on_sparse_hashmap() {
+        let mut m: HashMap<u64, u64, BuildHasherDefault<Identity>> =
+            HashMap::with_capacity_and_hasher(3_000_000, Default::default());
+        assert!(m.buckets.len() >= 1_000_000);
+        for i in 0..100 {
+            m.insert(i * 1000, i);
+        }
+        take_bucket_visits();
+        assert_eq!(m.iter().count(), 100);
+        // only the buckets holding an item are examined
+        assert_eq!(take_bucket_visits(), m.len());
+    }
+
+    #[test]
+    fn partition_even_odd() {
+        let m: HashMap<u32, u32> = (0..100).map(|i| (i, 2 * i)).collect();
+        let (even, odd) = m.partition(|k, _| k % 2 == 0);
+        assert_eq!(even.len(), 50);
+        assert_eq!(odd.len(), 50);
+        assert_eq!(even.len() + odd.len(), 100);
+        for i in 0..100 {
+            let (half, other) = if i % 2 == 0 {
+                (&even, &odd)
+            } else {
+                (&odd, &even)
+            };
+            assert_eq!(half.get(&i), Some(&(2 * i)));
+            assert!(!other.contains_key(&i));
+        }
+    }
+
+    #[test]
+    fn shrink_to_lower_bound() {
+        let mut m: HashMap<u32, u32> = (0..3000).map(|i| (i, i)).collect();
+        for i in 100..3000 {
+            m.remove(&i);
+        }
+        let before = m.buckets.len();
+        m.shrink_to(500);
+        assert!(m.buckets.len() < before);
+        assert!(m.buckets.len().is_power_of_two());
+        assert!(m.capacity() >= 500);
+        assert_eq!(m.len(), 100);
+        for i in 0..100 {
+            assert_eq!(m.get(&i), Some(&i));
+        }
+        m.shrink_to_fit();
+        assert_eq!(m.buckets.len(), capacity_for(100));
+        for i in 0..100 {
+            assert_eq!(m.get(&i), Some(&i));
+        }
+    }
+
+    #[test]
+    fn sort_entries_by_value() {
+        let scores: HashMap<&str, u32> =
+            [("Einar", 25), ("Olaf", 24), ("Harald", 12), ("Bjorn", 40)]
+                .iter()
+                .cloned()
+                .collect();
+        let top = scores.entries_sorted_by(|a, b| b.1.cmp(a.1));
+        assert_eq!(
+            top,
+            vec![
+                (&"Bjorn", &40),
+                (&"Einar", &25),
+                (&"Olaf", &24),
+                (&"Harald", &12)
+            ]
+        );
+    }
+
+    #[test]
+    fn remove_many_keys() {
+        let mut m: HashMap<u32, u32> = (0..10).map(|i| (i, i)).collect();
+        assert_eq!(m.remove_all(vec![1, 3, 5, 42, 99, 3]), 3);
+        assert_eq!(m.len(), 7);
+        for i in [1, 3, 5] {
+            assert!(!m.contains_key(&i));
+        }
+        assert!(m.contains_key(&0));
+
+        let mut empty: HashMap<u32, u32> = HashMap::new();
+        assert_eq!(empty.remove_all(0..10), 0);
+    }
+
+    #[test]
+    fn sorted_keys() {
+        let m: HashMap<u32, ()> = [7, 3, 9, 1, 4, 8, 2].iter().map(|&k| (k, ())).collect();
+        assert_eq!(m.into_sorted_keys(), vec![1, 2, 3, 4, 7, 8, 9]);
+    }
+
+    #[test]
+    fn cloned_iterator_continues_independently() {
+        let m: HashMap<u32, u32> = (0..20).map(|i| (i, i)).collect();
+        let mut it = (&m).into_iter();
+        for _ in 0..5 {
+            it.next();
+        }
+        let mut copy = it.clone();
+        assert_eq!(copy.next(), it.next());
+        let rest: Vec<_> = copy.collect();
+        assert_eq!(rest.len(), 14);
+        assert_eq!(it.collect::<Vec<_>>(), rest);
+
+        let mut entries = m.iter();
+        entries.next();
+        assert_eq!(entries.clone().count(), 19);
+        assert_eq!(entries.count(), 19);
+    }
+
+    #[test]
+    fn group_by_parity() {
+        let groups = HashMap::group_by((0..10).map(|i| (i % 2 == 0, i)));
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[&true], vec![0, 2, 4, 6, 8]);
+        assert_eq!(groups[&false], vec![1, 3, 5, 7, 9]);
+    }
+
+    #[test]
+    fn top_k_counts() {
+        let words = "a b c a b a d e a b c f";
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for w in words.split(' ') {
+            *counts.get_mut_or_insert_default(w) += 1;
+        }
+        let top = counts.into_top_k(3);
+        assert_eq!(top, vec![("a", 4), ("b", 3), ("c", 2)]);
+
+        let small: HashMap<u32, u32> = (0..2).map(|i| (i, i)).collect();
+        assert_eq!(small.into_top_k(5), vec![(1, 1), (0, 0)]);
+        let zero: HashMap<u32, u32> = (0..2).map(|i| (i, i)).collect();
+        assert!(zero.into_top_k(0).is_empty());
+    }
+
+    #[test]
+    fn split_into_parts() {
+        let m: HashMap<u32, u32> = (0..1000).map(|i| (i, 2 * i)).collect();
+        let mut parts = m.split(4);
+        assert_eq!(parts.len(), 4);
+        assert_eq!(parts.iter().map(HashMap::len).sum::<usize>(), 1000);
+        for i in 0..1000 {
+            let owners: Vec<_> = parts.iter().filter(|p| p.contains_key(&i)).collect();
+            assert_eq!(owners.len(), 1);
+            assert_eq!(owners[0][&i], 2 * i);
+        }
+        for part in parts.iter_mut() {
+            assert!(part.verify_cached_hashes());
+            part.insert(5000, 0);
+            assert_eq!(part.remove(&5000), Some(0));
+        }
+
+        let empty: HashMap<u32, u32> = HashMap::new();
+        let parts = empty.split(3);
+        assert_eq!(parts.len(), 3);
+        assert!(parts.iter().all(HashMap::is_empty));
+    }
+
     #[test]
     fn collect_to_hashmap() {
         let timber_resources: HashMap<&str, i32> =
@@ -275,7 +2943,7 @@ mod tests {
                 .iter()
                 .cloned()
                 .collect();
-        for (k, v) in timber_resources.into_iter() {
+        for (_, v) in (&timber_resources).into_iter() {
             assert!(v >= &10);
             assert!(v <= &100);
         }
@@ -0,0 +1,87 @@
+use super::*;
+
+use std::cmp::Eq;
+use std::hash::Hash;
+
+/// Housekeeping to run on a map with `HashMap::maintenance`.
+///
+/// Every option is off by default.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MaintenanceOpts {
+    /// Shrinks the bucket array as much as possible, see `shrink_to_fit`.
+    pub shrink_to_fit: bool,
+    /// Releases the spare capacity of every bucket.
+    pub compact_buckets: bool,
+    /// Replaces the seed of the map, see `with_seed`, and rehashes every
+    /// key with it.
+    pub reseed: Option<u64>,
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher> HashMap<K, V, S> {
+    /// Runs the housekeeping selected in `opts`.
+    ///
+    /// Reseeding hashes every key again, so that collisions found by
+    /// probing the previous seed do not carry over. It is done first, and
+    /// buckets are compacted last.
+    ///
+    /// Snapshots taken before reseeding can still be restored, at the cost
+    /// of hashing every key again, see `restore`.
+    pub fn maintenance(&mut self, opts: MaintenanceOpts) {
+        if let Some(seed) = opts.reseed {
+            self.reseed(seed);
+        }
+        if opts.shrink_to_fit {
+            self.shrink_to_fit();
+        }
+        if opts.compact_buckets {
+            for bkt in self.buckets.iter_mut() {
+                bkt.items.shrink_to_fit();
+                bkt.hashes.shrink_to_fit();
+            }
+        }
+    }
+
+    fn reseed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rehash_keys();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{HashMap, MaintenanceOpts};
+
+    #[test]
+    fn maintenance_on_churned_map() {
+        let mut m: HashMap<u32, u32> = HashMap::with_seed(1);
+        for i in 0..10_000 {
+            m.insert(i, i);
+        }
+        for i in 0..10_000 {
+            if i % 10 != 0 {
+                m.remove(&i);
+            }
+        }
+        let bucket_count = m.buckets.len();
+
+        m.maintenance(MaintenanceOpts {
+            shrink_to_fit: true,
+            compact_buckets: true,
+            reseed: Some(42),
+        });
+        assert_eq!(m.seed, 42);
+        assert!(m.buckets.len() < bucket_count);
+        assert!(m
+            .buckets
+            .iter()
+            .all(|bkt| bkt.items.capacity() == bkt.items.len()));
+        assert_eq!(m.len(), 1000);
+        assert_eq!(m.recount(), 1000);
+        assert!((0..10_000).all(|i| m.get(&i) == if i % 10 == 0 { Some(&i) } else { None }));
+        assert!(m.verify_cached_hashes());
+
+        m.maintenance(MaintenanceOpts::default());
+        assert_eq!(m.seed, 42);
+        assert_eq!(m.recount(), 1000);
+    }
+}
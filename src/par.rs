@@ -0,0 +1,43 @@
+use super::*;
+
+use rayon::prelude::*;
+use std::cmp::Eq;
+use std::hash::Hash;
+
+impl<K: Eq + Hash + Send, V: Send, S: BuildHasher> HashMap<K, V, S> {
+    /// Same as `retain`, with buckets processed in parallel.
+    pub fn par_retain<F>(&mut self, f: F) -> usize
+    where
+        F: Fn(&K, &mut V) -> bool + Sync,
+    {
+        let removed: usize = self
+            .buckets
+            .par_iter_mut()
+            .map(|bkt| bkt.retain(|k, v| f(k, v)))
+            .sum();
+        self.reindex_occupied();
+        debug_assert!(
+            self.num_items >= removed,
+            "item count out of sync, see `recount`"
+        );
+        self.num_items -= removed;
+        self.shrink_if_sparse();
+        removed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::HashMap;
+
+    #[test]
+    fn parallel_retain_matches_sequential() {
+        let mut par: HashMap<u32, u32> = (0..100_000).map(|i| (i, i)).collect();
+        let mut seq: HashMap<u32, u32> = (0..100_000).map(|i| (i, i)).collect();
+        assert_eq!(par.par_retain(|k, _| k % 2 == 0), 50_000);
+        assert_eq!(seq.retain(|k, _| k % 2 == 0), 50_000);
+        assert!(par == seq);
+        assert_eq!(par.len(), 50_000);
+        assert_eq!(par.recount(), 50_000);
+    }
+}
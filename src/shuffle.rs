@@ -0,0 +1,43 @@
+use super::*;
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::cmp::Eq;
+use std::hash::Hash;
+
+impl<K: Eq + Hash, V, S: BuildHasher> HashMap<K, V, S> {
+    /// Iterator on the entries of the map in an order drawn from `rng`,
+    /// independent of the bucket layout.
+    ///
+    /// The entries are collected and shuffled upfront, which allocates a
+    /// vector of `len()` references.
+    pub fn iter_shuffled<R: Rng + ?Sized>(&self, rng: &mut R) -> impl Iterator<Item = (&K, &V)> {
+        let mut entries: Vec<(&K, &V)> = Vec::with_capacity(self.num_items);
+        entries.extend(self.iter());
+        entries.shuffle(rng);
+        entries.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::HashMap;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn shuffled_yields_every_entry_once() {
+        let m: HashMap<u32, u32> = (0..1000).map(|i| (i, i * 2)).collect();
+        let mut rng = StdRng::seed_from_u64(7);
+        let shuffled: Vec<(&u32, &u32)> = m.iter_shuffled(&mut rng).collect();
+        assert_eq!(shuffled.len(), 1000);
+        let unique: BTreeSet<_> = shuffled.iter().cloned().collect();
+        assert_eq!(unique, m.iter().collect::<BTreeSet<_>>());
+        assert_ne!(shuffled, m.iter().collect::<Vec<_>>());
+        assert!(HashMap::<u32, u32>::new()
+            .iter_shuffled(&mut rng)
+            .next()
+            .is_none());
+    }
+}
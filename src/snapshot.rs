@@ -0,0 +1,121 @@
+use super::*;
+
+use std::cmp::Eq;
+use std::hash::Hash;
+
+/// Copy of the contents of a map, taken by `HashMap::snapshot`.
+///
+/// Buckets are copied with their cached hashes, so restoring into a map
+/// hashing keys the same way does not hash any key.
+#[derive(Clone)]
+pub struct MapSnapshot<K: Eq + Hash, V> {
+    buckets: Vec<Bucket<K, V>>,
+    num_items: usize,
+    /// Seed the cached hashes were computed with.
+    seed: u64,
+}
+
+impl<K: Eq + Hash, V> MapSnapshot<K, V> {
+    pub fn len(&self) -> usize {
+        self.num_items
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.num_items == 0
+    }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher> HashMap<K, V, S> {
+    /// Copies the entries and bucket layout of the map, to be restored
+    /// later with `restore`.
+    pub fn snapshot(&self) -> MapSnapshot<K, V>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        MapSnapshot {
+            buckets: self.buckets.clone(),
+            num_items: self.num_items,
+            seed: self.seed,
+        }
+    }
+
+    /// Replaces the contents of the map with `snapshot`, keeping the seed
+    /// and hasher of the map.
+    ///
+    /// The snapshot's buckets are moved in as they are, unless they were
+    /// hashed with another seed, as after a reseed, or with another hasher,
+    /// which is checked on one key. Every key is then hashed again.
+    pub fn restore(&mut self, snapshot: MapSnapshot<K, V>) {
+        self.buckets = snapshot.buckets;
+        self.num_items = snapshot.num_items;
+        self.reindex_occupied();
+        let stale = snapshot.seed != self.seed
+            || self
+                .buckets
+                .iter()
+                .flat_map(|bkt| bkt.hashes.iter().zip(&bkt.items))
+                .next()
+                .is_some_and(|(&hash, (key, _))| hash != self.hash_key(key));
+        if stale {
+            self.rehash_keys();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{HashMap, MaintenanceOpts};
+    use std::collections::hash_map::RandomState;
+
+    #[test]
+    fn restore_rolls_back_edits() {
+        let mut m: HashMap<u32, String> = (0..100).map(|i| (i, i.to_string())).collect();
+        let original = m.snapshot();
+        let bucket_count = m.buckets.len();
+        assert_eq!(original.len(), 100);
+
+        for i in 0..50 {
+            m.remove(&i);
+        }
+        for i in 100..5000 {
+            m.insert(i, String::new());
+        }
+        m.get_mut(&60).unwrap().push('!');
+        assert_ne!(m.buckets.len(), bucket_count);
+
+        m.restore(original.clone());
+        assert_eq!(m.len(), 100);
+        assert_eq!(m.buckets.len(), bucket_count);
+        assert!((0..100).all(|i| m.get(&i) == Some(&i.to_string())));
+        assert!(!m.contains_key(&100));
+        assert!(m.verify_cached_hashes());
+
+        m.clear_and_shrink();
+        m.restore(original);
+        assert_eq!(m.len(), 100);
+    }
+
+    #[test]
+    fn restore_after_reseed() {
+        let mut m: HashMap<u32, u32> = (0..100).map(|i| (i, i)).collect();
+        let original = m.snapshot();
+        m.maintenance(MaintenanceOpts {
+            reseed: Some(99),
+            ..MaintenanceOpts::default()
+        });
+        m.restore(original.clone());
+        assert_eq!(m.seed, 99);
+        assert!((0..100).all(|i| m.get(&i) == Some(&i)));
+        assert_eq!(m.insert(5, 50), Some(5));
+        assert_eq!(m.len(), 100);
+        assert!(m.verify_cached_hashes());
+
+        let mut other: HashMap<u32, u32, RandomState> = HashMap::with_hasher(RandomState::new());
+        other.restore(original);
+        assert!((0..100).all(|i| other.get(&i) == Some(&i)));
+        other.insert(5, 50);
+        assert_eq!(other.len(), 100);
+        assert!(other.verify_cached_hashes());
+    }
+}
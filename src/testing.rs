@@ -0,0 +1,99 @@
+//! Helpers shared by the unit tests.
+
+use std::cell::Cell;
+use std::hash::Hash;
+use std::hash::Hasher;
+
+/// Key whose hash ignores its value, so that all keys share one bucket.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Colliding(pub u32);
+
+impl Hash for Colliding {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        0u32.hash(state);
+    }
+}
+
+thread_local! {
+    static HASH_CALLS: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Key counting, per thread, how many times it is hashed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Counted(pub u32);
+
+impl Counted {
+    /// Number of hashes computed on this thread since the last call.
+    pub fn take_hash_calls() -> usize {
+        HASH_CALLS.with(|calls| calls.replace(0))
+    }
+}
+
+impl Hash for Counted {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        HASH_CALLS.with(|calls| calls.set(calls.get() + 1));
+        self.0.hash(state);
+    }
+}
+
+/// Hasher computing the 64-bit FNV-1a hash of the bytes written to it.
+pub struct Fnv(u64);
+
+impl Default for Fnv {
+    fn default() -> Self {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= u64::from(byte);
+            self.0 = self.0.wrapping_mul(0x100_0000_01b3);
+        }
+    }
+}
+
+/// Hasher returning the last integer written to it, so that integer keys
+/// land in the bucket given by their low bits.
+#[derive(Default)]
+pub struct Identity(u64);
+
+impl Hasher for Identity {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 << 8) | u64::from(byte);
+        }
+    }
+
+    fn write_u32(&mut self, n: u32) {
+        self.0 = u64::from(n);
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        self.0 = n;
+    }
+}
+
+thread_local! {
+    static BUCKET_VISITS: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Records that a map iterator examined a bucket on this thread.
+pub fn count_bucket_visit() {
+    BUCKET_VISITS.with(|visits| visits.set(visits.get() + 1));
+}
+
+/// Number of buckets examined by map iterators on this thread since the
+/// last call.
+pub fn take_bucket_visits() -> usize {
+    BUCKET_VISITS.with(|visits| visits.replace(0))
+}
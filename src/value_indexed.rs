@@ -0,0 +1,162 @@
+use super::*;
+
+use std::borrow::Borrow;
+use std::cmp::Eq;
+use std::hash::Hash;
+
+/// Hash map keeping its keys sorted by value in a secondary index, so
+/// that entries can be iterated by value without sorting them.
+///
+/// Every key and value is stored twice. Inserting or removing also updates
+/// the index, in `O(log n)` plus a scan of the keys with the same value.
+/// Values cannot be mutated in place, as the index would go stale.
+pub struct ValueIndexedMap<K: Eq + Hash, V> {
+    map: HashMap<K, V>,
+    by_value: BTreeMap<V, Vec<K>>,
+}
+
+impl<K: Eq + Hash, V> Default for ValueIndexedMap<K, V> {
+    fn default() -> Self {
+        ValueIndexedMap {
+            map: HashMap::new(),
+            by_value: BTreeMap::new(),
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone, V: Ord + Clone> ValueIndexedMap<K, V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Inserts the pair, returning the previous value of the key.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let previous = self.map.insert(key.clone(), value.clone());
+        if let Some(previous) = &previous {
+            self.unindex(&key, previous);
+        }
+        self.by_value.entry(value).or_default().push(key);
+        previous
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.map.get(key)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.map.contains_key(key)
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let value = self.map.remove(key)?;
+        self.unindex(key, &value);
+        Some(value)
+    }
+
+    /// Removes `key` from the keys at `value`.
+    fn unindex<Q>(&mut self, key: &Q, value: &V)
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        let keys = self
+            .by_value
+            .get_mut(value)
+            .expect("value missing from the index");
+        let i = keys
+            .iter()
+            .position(|k| k.borrow() == key)
+            .expect("key missing from the index");
+        keys.remove(i);
+        if keys.is_empty() {
+            self.by_value.remove(value);
+        }
+    }
+
+    /// Iterator on the entries by ascending value. Keys with equal values
+    /// come in the order they were given these values.
+    pub fn iter_by_value(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.by_value
+            .iter()
+            .flat_map(|(value, keys)| keys.iter().map(move |key| (key, value)))
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn as_map(&self) -> &HashMap<K, V> {
+        &self.map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ValueIndexedMap;
+
+    fn by_value(m: &ValueIndexedMap<&'static str, u32>) -> Vec<(&'static str, u32)> {
+        m.iter_by_value().map(|(&k, &v)| (k, v)).collect()
+    }
+
+    #[test]
+    fn value_order_across_updates() {
+        let mut m = ValueIndexedMap::new();
+        assert_eq!(m.insert("c", 3), None);
+        assert_eq!(m.insert("a", 1), None);
+        assert_eq!(m.insert("b", 2), None);
+        assert_eq!(m.insert("d", 1), None);
+        assert_eq!(by_value(&m), vec![("a", 1), ("d", 1), ("b", 2), ("c", 3)]);
+
+        assert_eq!(m.insert("a", 5), Some(1));
+        assert_eq!(by_value(&m), vec![("d", 1), ("b", 2), ("c", 3), ("a", 5)]);
+        assert_eq!(m.insert("b", 2), Some(2));
+        assert_eq!(by_value(&m), vec![("d", 1), ("b", 2), ("c", 3), ("a", 5)]);
+
+        assert_eq!(m.remove("d"), Some(1));
+        assert_eq!(m.remove("d"), None);
+        assert_eq!(by_value(&m), vec![("b", 2), ("c", 3), ("a", 5)]);
+        assert_eq!(m.get("a"), Some(&5));
+        assert_eq!(m.len(), 3);
+        assert_eq!(m.as_map().len(), 3);
+    }
+
+    #[test]
+    fn value_order_matches_sort() {
+        let mut m = ValueIndexedMap::new();
+        for i in 0..1000u32 {
+            m.insert(i, (i * 7919) % 101);
+        }
+        for i in (0..1000).step_by(3) {
+            m.remove(&i);
+        }
+        for i in (0..1000).step_by(5) {
+            m.insert(i, i % 13);
+        }
+        let mut sorted: Vec<(u32, u32)> = m.as_map().iter().map(|(&k, &v)| (k, v)).collect();
+        sorted.sort_unstable_by_key(|&(_, v)| v);
+        let indexed: Vec<(u32, u32)> = m.iter_by_value().map(|(&k, &v)| (k, v)).collect();
+        assert_eq!(indexed.len(), m.len());
+        assert!(indexed.windows(2).all(|w| w[0].1 <= w[1].1));
+        assert!(indexed.iter().all(|(k, v)| m.get(k) == Some(v)));
+        assert_eq!(
+            indexed.iter().map(|&(_, v)| v).collect::<Vec<_>>(),
+            sorted.iter().map(|&(_, v)| v).collect::<Vec<_>>()
+        );
+    }
+}
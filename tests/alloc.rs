@@ -0,0 +1,176 @@
+extern crate hashmapper;
+
+use hashmapper::*;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::hash::{BuildHasherDefault, Hasher};
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Allocator counting fresh allocations and reallocations, in total and on
+/// the current thread, and failing allocations larger than the current
+/// thread's limit or past its allocation budget.
+struct Counting;
+
+static ALLOCS: AtomicUsize = AtomicUsize::new(0);
+static REALLOCS: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static MAX_ALLOC: Cell<usize> = const { Cell::new(usize::MAX) };
+    static ALLOCS_LEFT: Cell<usize> = const { Cell::new(usize::MAX) };
+    static THREAD_ALLOCS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCS.fetch_add(1, Ordering::SeqCst);
+        let _ = THREAD_ALLOCS.try_with(|cell| cell.set(cell.get() + 1));
+        let max = MAX_ALLOC.try_with(Cell::get).unwrap_or(usize::MAX);
+        if layout.size() > max {
+            return ptr::null_mut();
+        }
+        let left = ALLOCS_LEFT.try_with(Cell::get).unwrap_or(usize::MAX);
+        if left == 0 {
+            return ptr::null_mut();
+        }
+        let _ = ALLOCS_LEFT.try_with(|cell| cell.set(left.saturating_sub(1)));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        REALLOCS.fetch_add(1, Ordering::SeqCst);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+/// Hasher returning the last integer written to it, so that a `u64` key is
+/// its own hash.
+#[derive(Default)]
+struct Identity(u64);
+
+impl Hasher for Identity {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, _: &[u8]) {
+        unreachable!("only u64 keys are hashed");
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        self.0 = n;
+    }
+}
+
+#[test]
+fn resize_allocates_each_bucket_once() {
+    let n = 100_000;
+    let mut map = HashMap::with_capacity(4 * n);
+    for i in 0..n {
+        map.insert(i, i);
+    }
+    let before = map.capacity();
+
+    let allocs = ALLOCS.load(Ordering::SeqCst);
+    let reallocs = REALLOCS.load(Ordering::SeqCst);
+    map.shrink_to_fit();
+    let allocs = ALLOCS.load(Ordering::SeqCst) - allocs;
+    let reallocs = REALLOCS.load(Ordering::SeqCst) - reallocs;
+
+    assert!(map.capacity() < before);
+    // items and cached hashes are allocated once per non-empty bucket
+    assert!(allocs < 2 * n);
+    assert_eq!(reallocs, 0);
+    for i in 0..n {
+        assert_eq!(map.get(&i), Some(&i));
+    }
+}
+
+#[test]
+fn checked_insert_returns_pair_on_failed_growth() {
+    let mut map = HashMap::new();
+    assert_eq!(map.checked_insert(0, 0), Ok(None));
+    // one item past the capacity, the next insertion grows the map
+    let full = map.capacity() + 1;
+    for i in 1..full {
+        assert_eq!(map.checked_insert(i, i), Ok(None));
+    }
+
+    // growing allocates a bucket array twice as large as the current one
+    MAX_ALLOC.with(|max| max.set(64 * 1024));
+    assert_eq!(map.checked_insert(full, full), Err((full, full)));
+    MAX_ALLOC.with(|max| max.set(usize::MAX));
+
+    assert_eq!(map.len(), full);
+    assert!((0..full).all(|i| map.get(&i) == Some(&i)));
+    assert!(!map.contains_key(&full));
+    assert_eq!(map.checked_insert(full, full), Ok(None));
+    assert_eq!(map.len(), full + 1);
+}
+
+#[test]
+fn checked_insert_returns_pair_on_failed_split() {
+    let mut map = HashMap::new();
+    assert_eq!(map.checked_insert(0, 0), Ok(None));
+    let capacity = map.capacity();
+    let full = capacity + 1;
+    for i in 1..full {
+        assert_eq!(map.checked_insert(i, i), Ok(None));
+    }
+
+    // only the bucket array can be allocated, not the split buckets
+    ALLOCS_LEFT.with(|left| left.set(1));
+    assert_eq!(map.checked_insert(full, full), Err((full, full)));
+    ALLOCS_LEFT.with(|left| left.set(usize::MAX));
+
+    assert_eq!(map.capacity(), capacity);
+    assert_eq!(map.len(), full);
+    assert!((0..full).all(|i| map.get(&i) == Some(&i)));
+    assert_eq!(map.checked_insert(full, full), Ok(None));
+    assert_eq!(map.len(), full + 1);
+}
+
+#[test]
+fn doubling_splits_buckets_in_place() {
+    let mut map: HashMap<u64, u64, BuildHasherDefault<Identity>> = HashMap::default();
+    map.insert(0, 0);
+    let full = map.capacity() as u64 + 1;
+    for i in 1..full {
+        map.insert(i, i);
+    }
+    let old_size = map.bucket_lengths().len();
+    let before: Vec<*const (u64, u64)> = (0..old_size)
+        .map(|idx| map.bucket_slice(idx).as_ptr())
+        .collect();
+
+    THREAD_ALLOCS.with(|allocs| allocs.set(0));
+    map.insert(full, full);
+    let allocs = THREAD_ALLOCS.with(Cell::get);
+
+    let mut lengths = map.bucket_lengths();
+    assert_eq!(lengths.len(), 2 * old_size);
+    let target = (full % lengths.len() as u64) as usize;
+    lengths[target] -= 1;
+    let split = (0..old_size)
+        .filter(|&idx| lengths[idx] != 0 && lengths[idx + old_size] != 0)
+        .count();
+    assert!(split > 0);
+    // the bucket array, then items and cached hashes of each moved half,
+    // and of the bucket of the new key if it was empty
+    let pushed = if lengths[target] == 0 { 2 } else { 0 };
+    assert_eq!(allocs, 1 + 2 * split + pushed);
+    for (idx, &ptr) in before.iter().enumerate() {
+        if lengths[idx] != 0 {
+            assert_eq!(map.bucket_slice(idx).as_ptr(), ptr);
+        }
+    }
+    assert!((0..=full).all(|i| map.get(&i) == Some(&i)));
+}
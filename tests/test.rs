@@ -115,6 +115,7 @@ fn custom_keys() {
 }
 
 #[test]
+#[allow(clippy::manual_range_contains)]
 fn key_value_iterators() {
     let mut map = HashMap::new();
     map.insert(1, 1);
@@ -128,3 +129,42 @@ fn key_value_iterators() {
         assert!(&1 <= k && k <= &5);
     }
 }
+
+#[test]
+fn string_keys_borrow_as_str() {
+    let mut map: HashMap<String, u32> = HashMap::new();
+    map.insert("viking".to_string(), 1);
+
+    assert_eq!(map.get("viking"), Some(&1));
+    assert!(map.contains_key("viking"));
+    assert!(!map.contains_key("saxon"));
+    assert_eq!(map["viking"], 1);
+}
+
+#[test]
+fn vec_keys_borrow_as_slice() {
+    let mut map: HashMap<Vec<u8>, u32> = HashMap::new();
+    map.insert(vec![1, 2, 3], 6);
+
+    let key: &[u8] = &[1, 2, 3];
+    assert_eq!(map.get(key), Some(&6));
+    assert!(map.contains_key(key));
+    assert!(!map.contains_key(&[1, 2][..]));
+    assert_eq!(map[key], 6);
+}
+
+#[test]
+fn boxed_keys_borrow_as_inner() {
+    let mut map: HashMap<Box<u64>, &str> = HashMap::new();
+    map.insert(Box::new(42), "answer");
+
+    assert_eq!(map.get(&42), Some(&"answer"));
+    assert!(map.contains_key(&42));
+    assert!(!map.contains_key(&7));
+    assert_eq!(map[&42], "answer");
+
+    let mut names: HashMap<Box<str>, u32> = HashMap::new();
+    names.insert("Einar".into(), 25);
+    assert_eq!(names.get("Einar"), Some(&25));
+    assert!(names.contains_key("Einar"));
+}